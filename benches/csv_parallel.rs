@@ -0,0 +1,65 @@
+//! Compares sequential and parallel csv conversion throughput, run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use rcli::{process_csv, CsvConvertOpts, OutputFormat};
+
+const ROWS: usize = 500_000;
+const RUNS: usize = 3;
+
+fn generate(rows: usize) -> Vec<u8> {
+    let mut data = b"Name,Position,DOB,Nationality,Kit Number,Bio\n".to_vec();
+    for i in 0..rows {
+        data.extend_from_slice(
+            format!(
+                "Player {0},Midfielder,\"Jan 1, 1990 (29)\",Italy,{1},\"Joined in {2},\nplays \"\"box to box\"\"\"\n",
+                i,
+                i % 99,
+                1990 + i % 30
+            )
+            .as_bytes(),
+        );
+    }
+    data
+}
+
+fn measure(data: &[u8], format: OutputFormat, threads: usize) -> Duration {
+    let opts = CsvConvertOpts {
+        infer_types: true,
+        threads,
+        ..Default::default()
+    };
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            process_csv(data, std::io::sink(), format, &opts).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let data = generate(ROWS);
+    let mb = data.len() as f64 / (1024.0 * 1024.0);
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    println!("{} rows, {:.1} MiB, {} cores", ROWS, mb, cores);
+    for format in [OutputFormat::Ndjson, OutputFormat::Json] {
+        let sequential = measure(&data, format, 1);
+        for threads in [1, 2, cores] {
+            let elapsed = if threads == 1 {
+                sequential
+            } else {
+                measure(&data, format, threads)
+            };
+            println!(
+                "{:<7} threads={:<3} {:>8.1?} {:>8.1} MiB/s  x{:.2}",
+                format.to_string(),
+                threads,
+                elapsed,
+                mb / elapsed.as_secs_f64(),
+                sequential.as_secs_f64() / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use std::str::FromStr;
+use std::{io::Write, str::FromStr};
 
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 
-use crate::CmdExector;
+use crate::{get_reader, get_writer_for, CmdExector};
 
 use super::verify_input_file;
 
@@ -20,7 +20,14 @@ pub enum Base64SubCommand {
 pub struct Base64EncodeOpts {
     #[arg(short, long, help = "Input string", value_parser = verify_input_file, default_value = "-")]
     pub input: String,
-    #[arg(short, long, help = "Output file path", value_parser = parse_base64_format, default_value = "standard")]
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(short, long, help = "Base64 format", value_parser = parse_base64_format, default_value = "standard")]
     pub format: Base64Format,
 }
 
@@ -28,7 +35,14 @@ pub struct Base64EncodeOpts {
 pub struct Base64DecodeOpts {
     #[arg(short, long, help = "Input file path", value_parser = verify_input_file, default_value = "-")]
     pub input: String,
-    #[arg(short, long, help = "Output file path", value_parser = parse_base64_format, default_value = "standard")]
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(short, long, help = "Base64 format", value_parser = parse_base64_format, default_value = "standard")]
     pub format: Base64Format,
 }
 
@@ -56,18 +70,18 @@ impl FromStr for Base64Format {
 
 impl CmdExector for Base64EncodeOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let mut reader = crate::get_reader(&self.input)?;
+        let mut reader = get_reader(&self.input)?;
         let ret = crate::process_encode(&mut reader, self.format)?;
-        println!("{}", ret);
+        writeln!(get_writer_for(&self.output, &[&self.input])?, "{}", ret)?;
         Ok(())
     }
 }
 
 impl CmdExector for Base64DecodeOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let mut reader = crate::get_reader(&self.input)?;
+        let mut reader = get_reader(&self.input)?;
         let ret = crate::process_decode(&mut reader, self.format)?;
-        println!("{}", ret);
+        writeln!(get_writer_for(&self.output, &[&self.input])?, "{}", ret)?;
         Ok(())
     }
 }
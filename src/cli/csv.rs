@@ -1,38 +1,711 @@
-use clap::{Parser, ValueEnum};
+use clap::{ArgAction, Args, Parser, ValueEnum};
 use core::fmt;
+use enum_dispatch::enum_dispatch;
+use serde::Deserialize;
+use std::{io::IsTerminal, path::Path, str::FromStr};
 
-use crate::CmdExector;
+use crate::{
+    get_content, get_reader, get_writer, get_writer_for, process_csv_concat, process_csv_diff,
+    process_csv_from, process_csv_generate, process_csv_group_by, process_csv_join,
+    process_csv_stats, process_sheet, stdout_width, Aggregate, CellRange, CmdExector,
+    CsvConvertOpts, CsvSchema, DiffOpts, Expr, GenColumn, SheetOpts,
+};
 
 use super::verify_input_file;
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExector)]
+pub enum CsvSubCommand {
+    #[command(
+        name = "from",
+        about = "Convert JSON, NDJSON or YAML records back to CSV"
+    )]
+    From(CsvFromOpts),
+    #[command(
+        name = "stats",
+        about = "Show per-column statistics or group-by aggregates"
+    )]
+    Stats(CsvStatsOpts),
+    #[command(name = "join", about = "Join two or more CSV files on a key column")]
+    Join(CsvJoinOpts),
+    #[command(name = "concat", about = "Append CSV files, aligning columns by name")]
+    Concat(CsvConcatOpts),
+    #[command(
+        name = "diff",
+        about = "Show rows added, removed or modified between two CSV files"
+    )]
+    Diff(CsvDiffOpts),
+    #[command(name = "generate", about = "Generate random records from column specs")]
+    Generate(CsvGenerateOpts),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum OutputFormat {
     Json,
     Yaml,
+    Toml,
+    Ndjson,
+    Msgpack,
+    Cbor,
+    Csv,
+    Table,
+    Parquet,
+    Arrow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    None,
+    Snappy,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    Json,
+    Ndjson,
+    Yaml,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Outer,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    /// Changed rows with old and new values per column
+    #[default]
+    Summary,
+    /// RFC 6902 JSON Patch against the rows keyed by the key columns
+    Patch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ColumnType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskRule {
+    Hash,
+    Redact,
+    Partial,
+    Year,
+    Month,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Auto,
+    Char(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Auto,
+    Label(&'static encoding_rs::Encoding),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CsvReaderOpts {
+    #[arg(
+        short,
+        long,
+        default_value = ",",
+        value_parser = parse_delimiter,
+        help = "Delimiter character, or \"auto\" to detect it"
+    )]
+    pub delimiter: Delimiter,
+    #[arg(
+        long,
+        action = ArgAction::Set,
+        default_value_t = true,
+        help = "Treat the first row as header, otherwise columns are named col_0, col_1, ..."
+    )]
+    pub header: bool,
+    #[arg(long, default_value = "\"", value_parser = parse_ascii_char, help = "Quote character")]
+    pub quote: u8,
+    #[arg(
+        long,
+        value_parser = parse_ascii_char,
+        help = "Escape character for quotes, quotes are escaped by doubling them if not set"
+    )]
+    pub escape: Option<u8>,
+    #[arg(long, value_parser = parse_ascii_char, help = "Skip lines starting with this character")]
+    pub comment: Option<u8>,
+    #[arg(
+        long,
+        value_parser = parse_encoding,
+        help = "Input encoding, e.g. latin1, utf-16, gbk, or \"auto\" to detect it [default: utf-8]"
+    )]
+    pub encoding: Option<TextEncoding>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct CsvQueryOpts {
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only output these columns, e.g. \"Name,Position\""
+    )]
+    pub select: Vec<String>,
+    #[arg(
+        long = "where",
+        help = "Only output rows matching the filter, e.g. \"Position == 'Goalkeeper'\"",
+        value_parser = parse_filter
+    )]
+    pub filter: Option<Expr>,
+    #[arg(long, help = "Sort rows by this column, numerically if possible")]
+    pub sort_by: Option<String>,
+    #[arg(long, help = "Sort in descending order", requires = "sort_by")]
+    pub desc: bool,
+    #[arg(long, visible_alias = "head", help = "Output at most this many rows")]
+    pub limit: Option<usize>,
+    #[arg(
+        long,
+        help = "Output only the last this many rows",
+        conflicts_with = "limit"
+    )]
+    pub tail: Option<usize>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ColumnarOpts {
+    #[arg(
+        long,
+        value_enum,
+        help = "Compression of parquet and arrow output [default: snappy for parquet, none for arrow]"
+    )]
+    pub compression: Option<Compression>,
+    #[arg(
+        long,
+        default_value_t = 65536,
+        value_parser = parse_positive,
+        help = "Rows per parquet row group or arrow record batch"
+    )]
+    pub row_group_size: usize,
+    #[arg(
+        long,
+        default_value_t = 1000,
+        value_parser = parse_positive,
+        help = "Rows the parquet or arrow schema and --infer-types column types are inferred from"
+    )]
+    pub sample_rows: usize,
 }
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct CsvOpts {
-    #[arg(short, long, help = "Input file path", value_parser = verify_input_file)]
-    pub input: String,
-    #[arg(short, long)]
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+    #[arg(short, long, help = "Input file path", value_parser = verify_input_file, required = true)]
+    pub input: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout [default: output.<format>, stdout for tables]"
+    )]
     pub output: Option<String>,
-    #[arg(long, help = "Output format", value_enum, default_value_t = OutputFormat::Json)]
-    pub format: OutputFormat,
-    #[arg(short, long, default_value_t = ',', help = "Delimiter character")]
-    pub delimiter: char,
-    #[arg(long, default_value_t = true, help = "Include header")]
-    pub header: bool,
+    #[arg(
+        long,
+        help = "Output format, inferred from the output file extension if omitted [default: json]",
+        value_enum
+    )]
+    pub format: Option<OutputFormat>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    #[arg(
+        long,
+        help = "Infer an integer, float, bool or date type per column instead of emitting strings, empty cells become null"
+    )]
+    pub infer_types: bool,
+    #[arg(
+        long = "column-type",
+        help = "Force the type of a column, e.g. \"Kit Number=int\" (string, int, float, bool, date)",
+        value_parser = parse_column_type
+    )]
+    pub column_types: Vec<(String, ColumnType)>,
+    #[arg(
+        long,
+        help = "Column spec file (yaml or json) every row is validated against",
+        value_parser = verify_input_file
+    )]
+    pub schema: Option<String>,
+    #[arg(
+        long,
+        help = "Abort on the first schema violation",
+        requires = "schema"
+    )]
+    pub strict: bool,
+    #[command(flatten)]
+    pub query: CsvQueryOpts,
+    #[command(flatten)]
+    pub columnar: ColumnarOpts,
+    #[arg(
+        long,
+        help = "Mask columns, e.g. \"Name=hash,DOB=year,Email=partial\" (hash, redact, partial, year, month); --where and --schema still see the original values",
+        value_parser = parse_mask,
+        value_delimiter = ','
+    )]
+    pub mask: Vec<(String, MaskRule)>,
+    #[arg(
+        long,
+        help = "Blake3 key file for hash masking, equal values get equal pseudonyms under the same key"
+    )]
+    pub mask_key: Option<String>,
+    #[arg(
+        long,
+        help = "Build nested objects and arrays from headers like \"address.city\" and \"tags[0]\""
+    )]
+    pub unflatten: bool,
+    #[arg(
+        long,
+        default_value = ".",
+        help = "Separator of nested header names",
+        requires = "unflatten"
+    )]
+    pub unflatten_separator: String,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Worker threads converting chunks of the input in parallel, 0 uses all cores"
+    )]
+    pub threads: usize,
+    #[arg(
+        long,
+        help = "Sheet name or zero-based index of an xlsx, xls or ods input [default: the first sheet]"
+    )]
+    pub sheet: Option<String>,
+    #[arg(
+        long,
+        help = "Cell range of the sheet to convert, e.g. \"B3:F20\"",
+        value_parser = parse_cell_range
+    )]
+    pub range: Option<CellRange>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvFromOpts {
+    #[arg(short, long, help = "Input file path", value_parser = verify_input_file, default_value = "-")]
+    pub input: String,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "output.csv"
+    )]
+    pub output: String,
+    #[arg(
+        long,
+        help = "Input format, inferred from the input file extension if omitted [default: json]",
+        value_enum
+    )]
+    pub format: Option<InputFormat>,
+    #[arg(short, long, default_value = ",", value_parser = parse_ascii_char, help = "Delimiter character")]
+    pub delimiter: u8,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    #[arg(short, long, help = "Input file path", value_parser = verify_input_file, default_value = "-")]
+    pub input: String,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(
+        long,
+        help = "Output format, inferred from the output file extension if omitted [default: json]",
+        value_enum
+    )]
+    pub format: Option<OutputFormat>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    #[arg(
+        long,
+        default_value_t = 5,
+        help = "Number of most frequent values to show for string columns"
+    )]
+    pub top: usize,
+    #[arg(long, help = "Aggregate rows grouped by the values of this column")]
+    pub group_by: Option<String>,
+    #[arg(
+        long = "agg",
+        help = "Aggregate to compute per group, e.g. \"count\", \"sum(Kit Number)\", \"avg(Kit Number)\" [default: count]",
+        value_parser = parse_aggregate,
+        requires = "group_by"
+    )]
+    pub aggregates: Vec<Aggregate>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    #[arg(short, long = "input", help = "Input file paths, the first one is the left side", value_parser = verify_input_file, num_args = 1.., required = true)]
+    pub inputs: Vec<String>,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(
+        long,
+        help = "Output format, inferred from the output file extension if omitted [default: json]",
+        value_enum
+    )]
+    pub format: Option<OutputFormat>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    #[arg(long, help = "Key column present in every input")]
+    pub on: String,
+    #[arg(long, value_enum, default_value_t = JoinType::Inner, help = "Join type")]
+    pub how: JoinType,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvConcatOpts {
+    #[arg(short, long = "input", help = "Input file paths", value_parser = verify_input_file, num_args = 1.., required = true)]
+    pub inputs: Vec<String>,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(
+        long,
+        help = "Output format, inferred from the output file extension if omitted [default: json]",
+        value_enum
+    )]
+    pub format: Option<OutputFormat>,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvGenerateOpts {
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(
+        long,
+        help = "Output format, inferred from the output file extension if omitted [default: csv]",
+        value_enum
+    )]
+    pub format: Option<OutputFormat>,
+    #[arg(
+        short,
+        long = "column",
+        help = "Column spec, e.g. \"Name=name\", \"Email=email\", \"Id=uuid\", \"Age=int:18..65\", \"Score=float:0..1\", \"Joined=date:2020-01-01..2024-12-31\", \"Team=enum:red|blue\"",
+        value_parser = parse_gen_column,
+        required = true
+    )]
+    pub columns: Vec<GenColumn>,
+    #[arg(short = 'n', long, default_value_t = 100, help = "Number of rows")]
+    pub rows: usize,
+    #[arg(long, help = "Seed for reproducible output")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    #[arg(help = "Old CSV file", value_parser = verify_input_file)]
+    pub old: String,
+    #[arg(help = "New CSV file", value_parser = verify_input_file)]
+    pub new: String,
+    #[arg(
+        short,
+        long,
+        help = "Output file path, or \"-\" for stdout",
+        default_value = "-"
+    )]
+    pub output: String,
+    #[arg(long, value_enum, default_value_t = DiffFormat::Summary, help = "Output format")]
+    pub format: DiffFormat,
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+    #[arg(
+        long = "key",
+        help = "Key columns identifying a row",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub keys: Vec<String>,
+    #[arg(
+        long,
+        help = "Columns to leave out of the comparison",
+        value_delimiter = ','
+    )]
+    pub ignore: Vec<String>,
+    #[arg(long, help = "Never color the summary")]
+    pub no_color: bool,
 }
 
 impl CmdExector for CsvOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let output = if let Some(output) = self.output {
-            output
+        if let Some(cmd) = self.cmd {
+            return cmd.execute().await;
+        }
+        let input = self
+            .input
+            .ok_or_else(|| anyhow::anyhow!("The input file is required"))?;
+        let format = self
+            .format
+            .or_else(|| self.output.as_deref().and_then(OutputFormat::from_path))
+            .unwrap_or(OutputFormat::Json);
+        let output = match self.output {
+            Some(output) => output,
+            None if format == OutputFormat::Table => "-".to_string(),
+            None => format!("output.{}", format),
+        };
+        let schema = match &self.schema {
+            Some(path) => Some(CsvSchema::try_new(get_reader(path)?)?),
+            None => None,
+        };
+        let mask_key = match &self.mask_key {
+            Some(path) => Some(get_content(path)?),
+            None => None,
+        };
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let reader = get_reader(&input)?;
+        let writer = get_writer_for(&output, &[&input])?;
+        let opts = CsvConvertOpts {
+            reader: self.reader,
+            infer_types: self.infer_types,
+            column_types: self.column_types,
+            schema,
+            strict: self.strict,
+            query: self.query,
+            columnar: self.columnar,
+            mask: self.mask,
+            mask_key,
+            unflatten: self.unflatten.then_some(self.unflatten_separator),
+            threads,
+            // only a terminal needs the table cut down to its width
+            table_width: (output == "-").then(stdout_width).flatten(),
+        };
+        let report = if self.sheet.is_some() || self.range.is_some() || is_spreadsheet(&input) {
+            let sheet = SheetOpts {
+                sheet: self.sheet,
+                range: self.range,
+            };
+            process_sheet(reader, writer, format, &opts, &sheet)?
         } else {
-            format!("output.{}", self.format)
+            crate::process_csv(reader, writer, format, &opts)?
+        };
+        if opts.schema.is_some() {
+            eprintln!("{}", report);
+        }
+        if output != "-" {
+            println!("{}", output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvFromOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = self
+            .format
+            .or_else(|| InputFormat::from_path(&self.input))
+            .unwrap_or(InputFormat::Json);
+        let reader = get_reader(&self.input)?;
+        let writer = get_writer_for(&self.output, &[&self.input])?;
+        process_csv_from(reader, writer, format, self.delimiter)?;
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvStatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = self
+            .format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap_or(OutputFormat::Json);
+        let reader = get_reader(&self.input)?;
+        let writer = get_writer_for(&self.output, &[&self.input])?;
+        match &self.group_by {
+            Some(column) => {
+                let mut aggregates = self.aggregates;
+                if aggregates.is_empty() {
+                    aggregates.push("count".parse()?);
+                }
+                process_csv_group_by(reader, writer, format, &self.reader, column, &aggregates)?
+            }
+            None => process_csv_stats(reader, writer, format, &self.reader, self.top)?,
+        }
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvJoinOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = self
+            .format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap_or(OutputFormat::Json);
+        // hash the smaller inputs and stream the largest one, stdin can only be streamed
+        let stream = self
+            .inputs
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, path)| match path.as_str() {
+                "-" => u64::MAX,
+                path => std::fs::metadata(path).map(|m| m.len()).unwrap_or_default(),
+            })
+            .map(|(i, _)| i)
+            .unwrap_or_default();
+        let readers = self
+            .inputs
+            .iter()
+            .map(|path| get_reader(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let inputs: Vec<&str> = self.inputs.iter().map(String::as_str).collect();
+        let writer = get_writer_for(&self.output, &inputs)?;
+        process_csv_join(
+            readers,
+            writer,
+            format,
+            &self.reader,
+            &self.on,
+            self.how,
+            stream,
+        )?;
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvConcatOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = self
+            .format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap_or(OutputFormat::Json);
+        let readers = self
+            .inputs
+            .iter()
+            .map(|path| get_reader(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let inputs: Vec<&str> = self.inputs.iter().map(String::as_str).collect();
+        let writer = get_writer_for(&self.output, &inputs)?;
+        process_csv_concat(readers, writer, format, &self.reader)?;
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvGenerateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = self
+            .format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap_or(OutputFormat::Csv);
+        let writer = get_writer(&self.output)?;
+        process_csv_generate(writer, format, &self.columns, self.rows, self.seed)?;
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvDiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let color = !self.no_color
+            && self.output == "-"
+            && std::env::var_os("NO_COLOR").is_none()
+            && std::io::stdout().is_terminal();
+        let opts = DiffOpts {
+            reader: self.reader,
+            keys: self.keys,
+            ignore: self.ignore,
+            format: self.format,
+            color,
         };
-        crate::process_csv(&self.input, output, self.format)
+        let old = get_reader(&self.old)?;
+        let new = get_reader(&self.new)?;
+        let writer = get_writer_for(&self.output, &[&self.old, &self.new])?;
+        process_csv_diff(old, new, writer, &opts)?;
+        if self.output != "-" {
+            println!("{}", self.output);
+        }
+        Ok(())
+    }
+}
+
+impl OutputFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        match ext.to_lowercase().as_str() {
+            "json" => Some(OutputFormat::Json),
+            "yaml" | "yml" => Some(OutputFormat::Yaml),
+            "toml" => Some(OutputFormat::Toml),
+            "ndjson" | "jsonl" => Some(OutputFormat::Ndjson),
+            "msgpack" | "mpk" => Some(OutputFormat::Msgpack),
+            "cbor" => Some(OutputFormat::Cbor),
+            "csv" => Some(OutputFormat::Csv),
+            "parquet" | "pq" => Some(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Some(OutputFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `path` names a workbook rather than delimited text.
+fn is_spreadsheet(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            matches!(
+                ext.to_lowercase().as_str(),
+                "xlsx" | "xlsm" | "xlsb" | "xls" | "ods"
+            )
+        })
+}
+
+impl InputFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        match ext.to_lowercase().as_str() {
+            "json" => Some(InputFormat::Json),
+            "ndjson" | "jsonl" => Some(InputFormat::Ndjson),
+            "yaml" | "yml" => Some(InputFormat::Yaml),
+            _ => None,
+        }
     }
 }
 
@@ -41,6 +714,14 @@ impl From<OutputFormat> for &'static str {
         match value {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Msgpack => "msgpack",
+            OutputFormat::Cbor => "cbor",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Table => "table",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
         }
     }
 }
@@ -50,3 +731,223 @@ impl fmt::Display for OutputFormat {
         write!(f, "{}", Into::<&'static str>::into(*self))
     }
 }
+
+impl Default for ColumnarOpts {
+    fn default() -> Self {
+        Self {
+            compression: None,
+            row_group_size: 65536,
+            sample_rows: 1000,
+        }
+    }
+}
+
+impl Default for CsvReaderOpts {
+    fn default() -> Self {
+        Self {
+            delimiter: Delimiter::Char(b','),
+            header: true,
+            quote: b'"',
+            escape: None,
+            comment: None,
+            encoding: None,
+        }
+    }
+}
+
+fn parse_ascii_char(s: &str) -> Result<u8, anyhow::Error> {
+    match s {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err(anyhow::anyhow!("Expected a single ASCII character: {}", s)),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<usize, anyhow::Error> {
+    match s.parse()? {
+        0 => anyhow::bail!("Must be at least 1"),
+        n => Ok(n),
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<Delimiter, anyhow::Error> {
+    if s.eq_ignore_ascii_case("auto") {
+        Ok(Delimiter::Auto)
+    } else {
+        Ok(Delimiter::Char(parse_ascii_char(s)?))
+    }
+}
+
+fn parse_encoding(s: &str) -> Result<TextEncoding, anyhow::Error> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(TextEncoding::Auto);
+    }
+    encoding_rs::Encoding::for_label(s.as_bytes())
+        .map(TextEncoding::Label)
+        .ok_or_else(|| anyhow::anyhow!("Unknown encoding: {}", s))
+}
+
+fn parse_cell_range(s: &str) -> Result<CellRange, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_gen_column(s: &str) -> Result<GenColumn, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_filter(s: &str) -> Result<Expr, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_aggregate(s: &str) -> Result<Aggregate, anyhow::Error> {
+    s.parse()
+}
+
+fn parse_column_type(s: &str) -> Result<(String, ColumnType), anyhow::Error> {
+    match s.rsplit_once('=') {
+        Some((name, ty)) if !name.is_empty() => Ok((name.to_string(), ty.parse()?)),
+        _ => Err(anyhow::anyhow!(
+            "Invalid column type: {}, expected <column>=<type>",
+            s
+        )),
+    }
+}
+
+fn parse_mask(s: &str) -> Result<(String, MaskRule), anyhow::Error> {
+    match s.rsplit_once('=') {
+        Some((name, rule)) if !name.is_empty() => Ok((name.to_string(), rule.parse()?)),
+        _ => Err(anyhow::anyhow!(
+            "Invalid mask: {}, expected <column>=<rule>",
+            s
+        )),
+    }
+}
+
+impl FromStr for MaskRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hash" => Ok(MaskRule::Hash),
+            "redact" => Ok(MaskRule::Redact),
+            "partial" => Ok(MaskRule::Partial),
+            "year" => Ok(MaskRule::Year),
+            "month" => Ok(MaskRule::Month),
+            _ => Err(anyhow::anyhow!("Invalid mask rule: {}", s)),
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "string" | "str" => Ok(ColumnType::String),
+            "int" | "integer" => Ok(ColumnType::Int),
+            "float" | "number" => Ok(ColumnType::Float),
+            "bool" | "boolean" => Ok(ColumnType::Bool),
+            "date" => Ok(ColumnType::Date),
+            _ => Err(anyhow::anyhow!("Invalid column type: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ColumnType {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ColumnType> for &'static str {
+    fn from(value: ColumnType) -> Self {
+        match value {
+            ColumnType::String => "string",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&'static str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_format_from_path() {
+        assert_eq!(OutputFormat::from_path("out.yml"), Some(OutputFormat::Yaml));
+        assert_eq!(
+            OutputFormat::from_path("/tmp/logs.JSONL"),
+            Some(OutputFormat::Ndjson)
+        );
+        assert_eq!(
+            OutputFormat::from_path("data.cbor"),
+            Some(OutputFormat::Cbor)
+        );
+        assert_eq!(OutputFormat::from_path("data.csv"), Some(OutputFormat::Csv));
+        assert_eq!(
+            OutputFormat::from_path("data.parquet"),
+            Some(OutputFormat::Parquet)
+        );
+        assert_eq!(
+            OutputFormat::from_path("data.feather"),
+            Some(OutputFormat::Arrow)
+        );
+        assert_eq!(OutputFormat::from_path("data.txt"), None);
+        assert_eq!(OutputFormat::from_path("data"), None);
+    }
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter("auto").unwrap(), Delimiter::Auto);
+        assert_eq!(parse_delimiter(";").unwrap(), Delimiter::Char(b';'));
+        assert_eq!(parse_delimiter("\t").unwrap(), Delimiter::Char(b'\t'));
+        assert_eq!(parse_delimiter("\\t").unwrap(), Delimiter::Char(b'\t'));
+        assert_eq!(parse_delimiter("tab").unwrap(), Delimiter::Char(b'\t'));
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("AUTO").unwrap(), TextEncoding::Auto);
+        assert_eq!(
+            parse_encoding("latin1").unwrap(),
+            TextEncoding::Label(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(
+            parse_encoding("utf-16").unwrap(),
+            TextEncoding::Label(encoding_rs::UTF_16LE)
+        );
+        assert_eq!(
+            parse_encoding("gbk").unwrap(),
+            TextEncoding::Label(encoding_rs::GBK)
+        );
+        assert!(parse_encoding("klingon").is_err());
+    }
+
+    #[test]
+    fn test_parse_column_type() {
+        assert_eq!(
+            parse_column_type("Kit Number=int").unwrap(),
+            ("Kit Number".to_string(), ColumnType::Int)
+        );
+        assert_eq!(
+            parse_column_type("a=b=Date").unwrap(),
+            ("a=b".to_string(), ColumnType::Date)
+        );
+        assert!(parse_column_type("Kit Number").is_err());
+        assert!(parse_column_type("=int").is_err());
+        assert!(parse_column_type("Kit Number=uint").is_err());
+    }
+}
@@ -1,9 +1,109 @@
-use crate::CmdExector;
-use clap::{ArgAction, Parser};
-use zxcvbn::zxcvbn;
+use crate::{get_content, CmdExector, OutputFormat, PassTemplate};
+use clap::{ArgAction, Args, Parser, ValueEnum};
+use enum_dispatch::enum_dispatch;
+
+use super::verify_input_file;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct GenPassOpts {
+    #[command(subcommand)]
+    pub cmd: Option<GenPassSubCommand>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of passwords to generate"
+    )]
+    pub count: u32,
+
+    #[arg(long, value_enum, default_value_t = GenPassFormat::Plain)]
+    pub output_format: GenPassFormat,
+
+    #[command(flatten)]
+    pub policy: PasswordPolicy,
+
+    #[command(flatten)]
+    pub passphrase: PassphraseOpts,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_template,
+        conflicts_with_all = ["length", "words"],
+        help = "Generate from a pattern, e.g. Cvccvc-99-!! or {word}{digit:3}{symbol}"
+    )]
+    pub template: Option<PassTemplate>,
+}
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExector)]
+pub enum GenPassSubCommand {
+    #[command(name = "check", about = "Analyze the strength of an existing password")]
+    Check(GenPassCheckOpts),
+    #[command(
+        name = "derive",
+        about = "Derive a reproducible site password from a master secret"
+    )]
+    Derive(GenPassDeriveOpts),
+}
+
+#[derive(Debug, Parser)]
+pub struct GenPassCheckOpts {
+    #[arg(
+        short,
+        long,
+        value_parser = verify_input_file,
+        default_value = "-",
+        help = "File holding the password, stdin by default"
+    )]
+    pub input: String,
+
+    #[arg(
+        short,
+        long = "word",
+        value_delimiter = ',',
+        help = "Words an attacker would try first, e.g. user or site names"
+    )]
+    pub words: Vec<String>,
+
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u8).range(0..=4),
+        help = "Fail unless the score is at least this (0-4)"
+    )]
+    pub min_score: Option<u8>,
+}
+
+#[derive(Debug, Parser)]
+pub struct GenPassDeriveOpts {
+    #[arg(
+        short,
+        long,
+        value_parser = verify_input_file,
+        default_value = "-",
+        help = "File holding the master secret, stdin by default"
+    )]
+    pub input: String,
+
+    #[arg(long, help = "Site the password is for, e.g. example.com")]
+    pub site: String,
+
+    #[arg(long, default_value = "", help = "User name at the site")]
+    pub user: String,
+
+    #[arg(long, default_value_t = 1, help = "Bump to rotate the password")]
+    pub counter: u32,
+
+    #[command(flatten)]
+    pub policy: PasswordPolicy,
+}
+
+/// Length and character classes of generated passwords.
+#[derive(Debug, Clone, Args)]
+pub struct PasswordPolicy {
     #[arg(short, long, default_value_t = 16, help = "Password length")]
     pub length: u8,
 
@@ -28,22 +128,194 @@ pub struct GenPassOpts {
 
     #[arg(long, action = ArgAction::Set, default_value_t = true, help = "Include symbols")]
     pub symbol: bool,
+
+    #[arg(long, help = "At least this many uppercase letters [default: 1]")]
+    pub min_upper: Option<u8>,
+
+    #[arg(long, help = "At least this many lowercase letters [default: 1]")]
+    pub min_lower: Option<u8>,
+
+    #[arg(long, help = "At least this many numbers [default: 1]")]
+    pub min_number: Option<u8>,
+
+    #[arg(long, help = "At least this many symbols [default: 1]")]
+    pub min_symbol: Option<u8>,
+
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        help = "Symbols to draw from [default: !@#$%^&*_]"
+    )]
+    pub symbols: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "",
+        allow_hyphen_values = true,
+        help = "Characters never to use"
+    )]
+    pub exclude: String,
+}
+
+/// Diceware passphrases drawn from the EFF large wordlist.
+#[derive(Debug, Clone, Args)]
+pub struct PassphraseOpts {
+    #[arg(
+        long,
+        conflicts_with = "length",
+        value_parser = clap::value_parser!(u8).range(1..),
+        help = "Generate a passphrase of this many words instead"
+    )]
+    pub words: Option<u8>,
+
+    #[arg(
+        long,
+        default_value = "-",
+        allow_hyphen_values = true,
+        requires = "words",
+        help = "Separator between words"
+    )]
+    pub separator: String,
+
+    #[arg(long, value_enum, default_value_t = Capitalize::None, requires = "words")]
+    pub capitalize: Capitalize,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        requires = "words",
+        help = "Append this many numbers to random words"
+    )]
+    pub add_numbers: u8,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        requires = "words",
+        help = "Append this many symbols to random words"
+    )]
+    pub add_symbols: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GenPassFormat {
+    /// One password per line, strength of a single password on stderr
+    Plain,
+    /// Array of password, score, crack time and entropy records
+    Json,
+    /// Password, score, crack time and entropy columns
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Capitalize {
+    /// Keep all words lowercase
+    None,
+    /// Capitalize the first letter of every word
+    First,
+    /// Uppercase every word
+    All,
+    /// Capitalize the first letter of random words
+    Random,
 }
 
 impl CmdExector for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let ret = crate::process_genpass(
-            self.length,
-            self.uppercase,
-            self.lowercase,
-            self.number,
-            self.symbol,
+        if let Some(cmd) = self.cmd {
+            return cmd.execute().await;
+        }
+        let passwords = crate::process_genpass_batch(
+            &self.policy,
+            &self.passphrase,
+            self.template.as_ref(),
+            self.count,
         )?;
-        println!("{}", ret);
+        let format = match self.output_format {
+            GenPassFormat::Plain => {
+                for p in &passwords {
+                    println!("{}", p.password);
+                }
+                // output password strength in stderr
+                if let [p] = passwords.as_slice() {
+                    eprintln!("Password strength: {}", p.score);
+                    eprintln!("Entropy: {:.1} bits", p.entropy);
+                }
+                return Ok(());
+            }
+            GenPassFormat::Json => OutputFormat::Json,
+            GenPassFormat::Csv => OutputFormat::Csv,
+        };
+        crate::write_passwords(std::io::stdout().lock(), &passwords, format)
+    }
+}
 
-        // output password strength in stderr
-        let estimate = zxcvbn(&ret, &[])?;
-        eprintln!("Password strength: {}", estimate.score());
+impl CmdExector for GenPassCheckOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let content = String::from_utf8(get_content(&self.input)?)?;
+        // only the trailing newline of `echo` or a file, other whitespace counts
+        let password = content.trim_end_matches(['\n', '\r']);
+        let score = crate::process_password_check(password, &self.words, std::io::stdout().lock())?;
+        if let Some(min) = self.min_score {
+            anyhow::ensure!(
+                score >= min,
+                "Password score {} is below the minimum of {}",
+                score,
+                min
+            );
+        }
         Ok(())
     }
 }
+
+fn parse_template(s: &str) -> Result<PassTemplate, anyhow::Error> {
+    s.parse()
+}
+
+impl CmdExector for GenPassDeriveOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut secret = get_content(&self.input)?;
+        // drop the trailing newline of `echo` or a file
+        while secret.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+            secret.pop();
+        }
+        let ret = crate::process_genpass_derive(
+            &secret,
+            &self.site,
+            &self.user,
+            self.counter,
+            &self.policy,
+        )?;
+        println!("{}", ret);
+        Ok(())
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            uppercase: true,
+            lowercase: true,
+            number: true,
+            symbol: true,
+            min_upper: None,
+            min_lower: None,
+            min_number: None,
+            min_symbol: None,
+            symbols: None,
+            exclude: String::new(),
+        }
+    }
+}
+
+impl Default for PassphraseOpts {
+    fn default() -> Self {
+        Self {
+            words: None,
+            separator: "-".to_string(),
+            capitalize: Capitalize::None,
+            add_numbers: 0,
+            add_symbols: 0,
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use std::io::Write;
+
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 use jsonwebtoken::Algorithm;
 
-use crate::{process_jwt_sign, process_jwt_verify, CmdExector};
+use crate::{get_writer, process_jwt_sign, process_jwt_verify, CmdExector};
 
 #[derive(Debug, Parser)]
 #[enum_dispatch(CmdExector)]
@@ -23,6 +25,8 @@ pub struct SignOpts {
     pub exp: usize,
     #[arg(long, help = "JWT audience", default_value = "rcli")]
     pub aud: String,
+    #[arg(short, long, help = "Output file", default_value = "-")]
+    pub output: String,
 }
 
 #[derive(Debug, Parser)]
@@ -62,7 +66,7 @@ fn parse_duration(s: &str) -> Result<usize, String> {
 impl CmdExector for SignOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let token = process_jwt_sign(self.sub, self.exp, self.aud, self.alg)?;
-        println!("{}", token);
+        writeln!(get_writer(&self.output)?, "{}", token)?;
 
         Ok(())
     }
@@ -7,7 +7,7 @@ mod text;
 
 use std::path::{Path, PathBuf};
 
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use enum_dispatch::enum_dispatch;
 
 pub use self::{base64::*, csv::*, genpass::*, http::*, jwt::*, text::*};
@@ -19,12 +19,14 @@ pub struct Opts {
     pub cmd: SubCommand,
 }
 
+// parsed once per run, boxing the csv options isn't worth it
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 #[enum_dispatch(CmdExector)]
 pub enum SubCommand {
-    #[command(name = "csv", about = "Convert CSV to JSON")]
+    #[command(name = "csv", about = "Convert CSV to JSON, YAML and other formats")]
     Csv(CsvOpts),
-    #[command(name = "genpass", about = "Generate a random password")]
+    #[command(name = "genpass", about = "Generate, check or derive passwords")]
     GenPass(GenPassOpts),
     #[command(subcommand, about = "Base64 encode/decode")]
     Base64(Base64SubCommand),
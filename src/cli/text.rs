@@ -1,4 +1,4 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{io::Write, path::PathBuf, str::FromStr};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use clap::Parser;
@@ -6,8 +6,8 @@ use enum_dispatch::enum_dispatch;
 use tokio::fs;
 
 use crate::{
-    get_content, get_reader, process_text_decrypt, process_text_encrypt, process_text_key_generate,
-    process_text_sign, process_text_verify, CmdExector,
+    get_content, get_reader, get_writer_for, process_text_decrypt, process_text_encrypt,
+    process_text_key_generate, process_text_sign, process_text_verify, CmdExector,
 };
 
 use super::{verify_input_file, verify_path};
@@ -37,6 +37,8 @@ pub enum TextSubCommand {
 pub struct TextSignOpts {
     #[arg(short, long, default_value = "-", value_parser = verify_input_file)]
     pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
     #[arg(short, long, value_parser = verify_input_file)]
     pub key: String,
     #[arg(long, default_value = "blake3", value_parser = parse_text_sign_format)]
@@ -67,6 +69,8 @@ pub struct KeyGenerateOpts {
 pub struct EncryptOpts {
     #[arg(short, long, default_value = "-", value_parser = verify_input_file)]
     pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
     #[arg(short, long, value_parser = verify_input_file, default_value = "fixtures/b64.txt")]
     pub key: String,
 }
@@ -75,6 +79,8 @@ pub struct EncryptOpts {
 pub struct DecryptOpts {
     #[arg(short, long, default_value = "-", value_parser = verify_input_file)]
     pub input: String,
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
     #[arg(short, long, value_parser = verify_input_file, default_value = "fixtures/b64.txt")]
     pub key: String,
 }
@@ -108,7 +114,7 @@ impl CmdExector for TextSignOpts {
         let sig = process_text_sign(&mut reader, &key, self.format)?;
         // base64 output
         let encoded = URL_SAFE_NO_PAD.encode(sig);
-        println!("{}", encoded);
+        writeln!(get_writer_for(&self.output, &[&self.input])?, "{}", encoded)?;
         Ok(())
     }
 }
@@ -144,7 +150,7 @@ impl CmdExector for EncryptOpts {
         let key = get_content(&self.key)?;
         let encrypted = process_text_encrypt(&mut reader, &key)?;
         let encoded = URL_SAFE_NO_PAD.encode(encrypted);
-        println!("{}", encoded);
+        writeln!(get_writer_for(&self.output, &[&self.input])?, "{}", encoded)?;
         Ok(())
     }
 }
@@ -154,7 +160,11 @@ impl CmdExector for DecryptOpts {
         let encrypted = URL_SAFE_NO_PAD.decode(get_content(&self.input)?)?;
         let key = get_content(&self.key)?;
         let decrypted = process_text_decrypt(&mut encrypted.as_slice(), &key)?;
-        println!("{}", String::from_utf8_lossy(&decrypted));
+        writeln!(
+            get_writer_for(&self.output, &[&self.input])?,
+            "{}",
+            String::from_utf8_lossy(&decrypted)
+        )?;
         Ok(())
     }
 }
@@ -0,0 +1,526 @@
+use anyhow::Result;
+use arrow_array::{
+    builder::{
+        BooleanBuilder, Date32Builder, Float64Builder, Int64Builder, StringBuilder,
+        TimestampMillisecondBuilder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::{
+    writer::{FileWriter, IpcWriteOptions},
+    CompressionType,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression as ParquetCompression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use serde_json::{Map, Value};
+use std::{io::Write, mem, sync::Arc};
+
+use crate::{ColumnType, ColumnarOpts, Compression, OutputFormat};
+
+use super::{
+    csv_types::{convert_value, infer_type},
+    csv_writer::RecordWriter,
+};
+
+/// Writes Parquet or Arrow IPC files. The schema is inferred from the first
+/// `sample_rows` records, which are kept until then; later records must fit it.
+pub struct ColumnarWriter<W: Write> {
+    format: OutputFormat,
+    opts: ColumnarOpts,
+    writer: Option<W>,
+    sample: Vec<Map<String, Value>>,
+    encoder: Option<Encoder<W>>,
+    columns: Vec<Column>,
+    rows: usize,
+    written: usize,
+}
+
+enum Encoder<W: Write> {
+    // the parquet writer wants a `Send` sink, encoded row groups are moved on from a buffer
+    Parquet(ArrowWriter<Vec<u8>>, W),
+    Arrow(FileWriter<W>),
+}
+
+struct Column {
+    name: String,
+    builder: ColumnBuilder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Int,
+    Float,
+    Bool,
+    Date,
+    Timestamp,
+    Utf8,
+}
+
+enum ColumnBuilder {
+    Int(Int64Builder),
+    Float(Float64Builder),
+    Bool(BooleanBuilder),
+    Date(Date32Builder),
+    Timestamp(TimestampMillisecondBuilder),
+    Utf8(StringBuilder),
+}
+
+impl<W: Write> ColumnarWriter<W> {
+    pub fn new(writer: W, format: OutputFormat, opts: ColumnarOpts) -> Self {
+        Self {
+            format,
+            opts,
+            writer: Some(writer),
+            sample: Vec::new(),
+            encoder: None,
+            columns: Vec::new(),
+            rows: 0,
+            written: 0,
+        }
+    }
+
+    /// Infers the schema from the sampled records and feeds them to the encoder.
+    fn start(&mut self) -> Result<()> {
+        let sample = mem::take(&mut self.sample);
+        let mut kinds: Vec<(String, Option<ColumnKind>)> = Vec::new();
+        for record in &sample {
+            for (key, value) in record {
+                let kind = value_kind(value);
+                match kinds.iter_mut().find(|(name, _)| name == key) {
+                    Some((_, k)) => *k = merge_kinds(*k, kind),
+                    None => kinds.push((key.clone(), kind)),
+                }
+            }
+        }
+        self.columns = kinds
+            .into_iter()
+            .map(|(name, kind)| Column {
+                name,
+                builder: ColumnBuilder::new(kind.unwrap_or(ColumnKind::Utf8)),
+            })
+            .collect();
+        let schema = self.schema();
+        let writer = self
+            .writer
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Columnar writer already finished"))?;
+        self.encoder = Some(match self.format {
+            OutputFormat::Parquet => {
+                let compression = match self.opts.compression {
+                    None | Some(Compression::Snappy) => ParquetCompression::SNAPPY,
+                    Some(Compression::Zstd) => ParquetCompression::ZSTD(ZstdLevel::default()),
+                    Some(Compression::None) => ParquetCompression::UNCOMPRESSED,
+                };
+                let props = WriterProperties::builder()
+                    .set_compression(compression)
+                    .set_max_row_group_row_count(Some(self.opts.row_group_size))
+                    .build();
+                Encoder::Parquet(
+                    ArrowWriter::try_new(Vec::new(), schema, Some(props))?,
+                    writer,
+                )
+            }
+            _ => {
+                let compression = match self.opts.compression {
+                    None | Some(Compression::None) => None,
+                    Some(Compression::Zstd) => Some(CompressionType::ZSTD),
+                    Some(Compression::Snappy) => {
+                        anyhow::bail!("Arrow IPC files support zstd compression only")
+                    }
+                };
+                let options = IpcWriteOptions::default().try_with_compression(compression)?;
+                Encoder::Arrow(FileWriter::try_new_with_options(writer, &schema, options)?)
+            }
+        });
+        for record in &sample {
+            self.append(record)?;
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Map<String, Value>) -> Result<()> {
+        if let Some(key) = record
+            .keys()
+            .find(|key| !self.columns.iter().any(|c| &c.name == *key))
+        {
+            anyhow::bail!(
+                "Column '{}' is missing from the schema inferred from the first {} rows, raise --sample-rows",
+                key,
+                self.opts.sample_rows
+            );
+        }
+        for column in &mut self.columns {
+            let value = record.get(&column.name).unwrap_or(&Value::Null);
+            column.builder.append(value).map_err(|e| {
+                anyhow::anyhow!(
+                    "{} for column '{}' in row {}, the schema was inferred from the first {} rows, raise --sample-rows",
+                    e,
+                    column.name,
+                    self.written + self.rows + 1,
+                    self.opts.sample_rows
+                )
+            })?;
+        }
+        self.rows += 1;
+        if self.rows >= self.opts.row_group_size {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .map(|c| Field::new(&c.name, c.builder.data_type(), true))
+            .collect();
+        Arc::new(Schema::new(fields))
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let schema = self.schema();
+        let arrays: Vec<ArrayRef> = self
+            .columns
+            .iter_mut()
+            .map(|c| c.builder.finish())
+            .collect();
+        let batch = RecordBatch::try_new(schema, arrays)?;
+        match &mut self.encoder {
+            Some(Encoder::Parquet(parquet, writer)) => {
+                parquet.write(&batch)?;
+                parquet.flush()?;
+                writer.write_all(&mem::take(parquet.inner_mut()))?;
+            }
+            Some(Encoder::Arrow(ipc)) => ipc.write(&batch)?,
+            None => {}
+        }
+        self.written += self.rows;
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for ColumnarWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("{} records must be objects", self.format);
+        };
+        if self.encoder.is_some() {
+            return self.append(map);
+        }
+        self.sample.push(map.clone());
+        if self.sample.len() >= self.opts.sample_rows {
+            self.start()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.encoder.is_none() {
+            self.start()?;
+        }
+        self.flush_batch()?;
+        match self.encoder.take() {
+            Some(Encoder::Parquet(mut parquet, mut writer)) => {
+                parquet.finish()?;
+                writer.write_all(parquet.inner())?;
+                writer.flush()?;
+            }
+            Some(Encoder::Arrow(mut ipc)) => {
+                ipc.finish()?;
+                ipc.get_mut().flush()?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnKind) -> Self {
+        match kind {
+            ColumnKind::Int => Self::Int(Int64Builder::new()),
+            ColumnKind::Float => Self::Float(Float64Builder::new()),
+            ColumnKind::Bool => Self::Bool(BooleanBuilder::new()),
+            ColumnKind::Date => Self::Date(Date32Builder::new()),
+            ColumnKind::Timestamp => {
+                Self::Timestamp(TimestampMillisecondBuilder::new().with_timezone("UTC"))
+            }
+            ColumnKind::Utf8 => Self::Utf8(StringBuilder::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Self::Int(_) => DataType::Int64,
+            Self::Float(_) => DataType::Float64,
+            Self::Bool(_) => DataType::Boolean,
+            Self::Date(_) => DataType::Date32,
+            Self::Timestamp(_) => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            Self::Utf8(_) => DataType::Utf8,
+        }
+    }
+
+    /// Appends `value`, converting strings the way `--column-type` does.
+    fn append(&mut self, value: &Value) -> Result<()> {
+        if let Self::Utf8(b) = self {
+            match value {
+                Value::Null => b.append_null(),
+                Value::String(s) => b.append_value(s),
+                v => b.append_value(v.to_string()),
+            }
+            return Ok(());
+        }
+        let value = match value {
+            Value::String(s) => convert_value(s, self.column_type())?,
+            v => v.clone(),
+        };
+        let ty = self.data_type();
+        let invalid = || anyhow::anyhow!("Invalid {} value '{}'", ty, value);
+        match self {
+            _ if value.is_null() => self.append_null(),
+            Self::Int(b) => b.append_value(value.as_i64().ok_or_else(invalid)?),
+            Self::Float(b) => b.append_value(value.as_f64().ok_or_else(invalid)?),
+            Self::Bool(b) => b.append_value(value.as_bool().ok_or_else(invalid)?),
+            Self::Date(b) => {
+                let date = value.as_str().and_then(parse_date).ok_or_else(invalid)?;
+                b.append_value(date);
+            }
+            Self::Timestamp(b) => {
+                let ts = value
+                    .as_str()
+                    .and_then(parse_timestamp)
+                    .ok_or_else(invalid)?;
+                b.append_value(ts);
+            }
+            Self::Utf8(_) => unreachable!("handled above"),
+        }
+        Ok(())
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            Self::Int(b) => b.append_null(),
+            Self::Float(b) => b.append_null(),
+            Self::Bool(b) => b.append_null(),
+            Self::Date(b) => b.append_null(),
+            Self::Timestamp(b) => b.append_null(),
+            Self::Utf8(b) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Int(b) => Arc::new(b.finish()),
+            Self::Float(b) => Arc::new(b.finish()),
+            Self::Bool(b) => Arc::new(b.finish()),
+            Self::Date(b) => Arc::new(b.finish()),
+            Self::Timestamp(b) => Arc::new(b.finish()),
+            Self::Utf8(b) => Arc::new(b.finish()),
+        }
+    }
+
+    fn column_type(&self) -> ColumnType {
+        match self {
+            Self::Int(_) => ColumnType::Int,
+            Self::Float(_) => ColumnType::Float,
+            Self::Bool(_) => ColumnType::Bool,
+            Self::Date(_) | Self::Timestamp(_) => ColumnType::Date,
+            Self::Utf8(_) => ColumnType::String,
+        }
+    }
+}
+
+/// The narrowest column kind holding `value`, nulls and empty cells have none.
+fn value_kind(value: &Value) -> Option<ColumnKind> {
+    let kind = match value {
+        Value::Null => return None,
+        Value::Bool(_) => ColumnKind::Bool,
+        Value::Number(n) if n.is_i64() => ColumnKind::Int,
+        Value::Number(_) => ColumnKind::Float,
+        Value::String(s) => match infer_type(s)? {
+            ColumnType::Int => ColumnKind::Int,
+            ColumnType::Float => ColumnKind::Float,
+            ColumnType::Bool => ColumnKind::Bool,
+            ColumnType::Date if parse_date(s).is_some() => ColumnKind::Date,
+            ColumnType::Date if parse_timestamp(s).is_some() => ColumnKind::Timestamp,
+            ColumnType::Date | ColumnType::String => ColumnKind::Utf8,
+        },
+        Value::Array(_) | Value::Object(_) => ColumnKind::Utf8,
+    };
+    Some(kind)
+}
+
+fn merge_kinds(a: Option<ColumnKind>, b: Option<ColumnKind>) -> Option<ColumnKind> {
+    use ColumnKind::*;
+    match (a, b) {
+        (None, k) | (k, None) => k,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(Int), Some(Float)) | (Some(Float), Some(Int)) => Some(Float),
+        (Some(Date), Some(Timestamp)) | (Some(Timestamp), Some(Date)) => Some(Timestamp),
+        _ => Some(Utf8),
+    }
+}
+
+/// Days since the unix epoch.
+fn parse_date(s: &str) -> Option<i32> {
+    let date = NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok()?;
+    Some((date - NaiveDate::default()).num_days() as i32)
+}
+
+/// Milliseconds since the unix epoch, naive date times are taken as UTC.
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.timestamp_millis());
+    }
+    let dt = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })?;
+    Some(dt.and_utc().timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{cast::AsArray, types::*, Array};
+    use arrow_ipc::reader::FileReader;
+    use bytes::Bytes;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({ "Name": "Szczesny", "Kit Number": "1", "Rating": 7, "DOB": "1990-04-18", "Captain": false }),
+            json!({ "Name": "Perin", "Kit Number": "37", "Rating": 6.5, "DOB": null, "Captain": true }),
+            json!({ "Name": "Pinsoglio", "Kit Number": "", "Rating": null, "DOB": "1990-01-16", "Captain": null }),
+        ]
+    }
+
+    fn write(format: OutputFormat, opts: ColumnarOpts, records: &[Value]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut writer = ColumnarWriter::new(&mut buf, format, opts);
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        Ok(buf)
+    }
+
+    fn read(format: OutputFormat, buf: Vec<u8>) -> Result<Vec<RecordBatch>> {
+        if format == OutputFormat::Arrow {
+            return Ok(FileReader::try_new(Cursor::new(buf), None)?.collect::<Result<_, _>>()?);
+        }
+        // one batch per row group
+        let buf = Bytes::from(buf);
+        let groups = ParquetRecordBatchReaderBuilder::try_new(buf.clone())?
+            .metadata()
+            .num_row_groups();
+        let mut batches = Vec::new();
+        for i in 0..groups {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(buf.clone())?
+                .with_row_groups(vec![i])
+                .build()?;
+            for batch in reader {
+                batches.push(batch?);
+            }
+        }
+        Ok(batches)
+    }
+
+    #[test]
+    fn test_columnar_writer_infers_schema() -> Result<()> {
+        for format in [OutputFormat::Parquet, OutputFormat::Arrow] {
+            for compression in [None, Some(Compression::Zstd), Some(Compression::None)] {
+                let opts = ColumnarOpts {
+                    compression,
+                    row_group_size: 2,
+                    ..Default::default()
+                };
+                let batches = read(format, write(format, opts, &records())?)?;
+                assert_eq!(
+                    batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(),
+                    [2, 1]
+                );
+                let schema = batches[0].schema();
+                let types: Vec<_> = schema.fields().iter().map(|f| f.data_type()).collect();
+                assert_eq!(
+                    types,
+                    [
+                        &DataType::Utf8,
+                        &DataType::Int64,
+                        &DataType::Float64,
+                        &DataType::Date32,
+                        &DataType::Boolean
+                    ]
+                );
+                let kits = batches[0].column(1).as_primitive::<Int64Type>();
+                assert_eq!(kits.values(), &[1, 37]);
+                assert!(batches[1].column(1).is_null(0));
+                let dob = batches[0].column(3).as_primitive::<Date32Type>();
+                assert_eq!(dob.value(0), 7412);
+                assert!(dob.is_null(1));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_writer_rejects_rows_outside_the_sample() -> Result<()> {
+        let opts = ColumnarOpts {
+            sample_rows: 1,
+            ..Default::default()
+        };
+        let records = vec![json!({ "Kit Number": "1" }), json!({ "Kit Number": "A" })];
+        let err = write(OutputFormat::Parquet, opts.clone(), &records).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid int value 'A' for column 'Kit Number' in row 2, the schema was inferred from the first 1 rows, raise --sample-rows"
+        );
+        let records = vec![json!({ "Kit Number": "1" }), json!({ "Team": "Juventus" })];
+        assert!(write(OutputFormat::Arrow, opts.clone(), &records).is_err());
+        let opts = ColumnarOpts {
+            compression: Some(Compression::Snappy),
+            ..Default::default()
+        };
+        assert!(write(OutputFormat::Arrow, opts, &records).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_writer_empty_input() -> Result<()> {
+        for format in [OutputFormat::Parquet, OutputFormat::Arrow] {
+            let buf = write(format, ColumnarOpts::default(), &[])?;
+            assert!(read(format, buf)?.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_kind() {
+        assert_eq!(
+            value_kind(&json!("2019-05-01T10:00:00Z")),
+            Some(ColumnKind::Timestamp)
+        );
+        assert_eq!(value_kind(&json!("007")), Some(ColumnKind::Utf8));
+        assert_eq!(value_kind(&json!("")), None);
+        assert_eq!(
+            merge_kinds(Some(ColumnKind::Date), Some(ColumnKind::Timestamp)),
+            Some(ColumnKind::Timestamp)
+        );
+        assert_eq!(parse_timestamp("1970-01-02"), Some(86_400_000));
+    }
+}
@@ -0,0 +1,441 @@
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+};
+
+use crate::{CsvReaderOpts, DiffFormat};
+
+use super::build_csv_reader;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Default)]
+pub struct DiffOpts {
+    pub reader: CsvReaderOpts,
+    /// Columns identifying a row, composite keys are shown comma separated with
+    /// values quoted as in csv when they hold commas or quotes
+    pub keys: Vec<String>,
+    /// Columns left out of the comparison
+    pub ignore: Vec<String>,
+    pub format: DiffFormat,
+    pub color: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiffStats {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    Added(String, Map<String, Value>),
+    Removed(String, Map<String, Value>),
+    Modified(String, Vec<ColumnChange>),
+}
+
+#[derive(Debug, PartialEq)]
+struct ColumnChange {
+    column: String,
+    old: String,
+    new: String,
+}
+
+/// A column of both files and where it sits in each.
+struct Column {
+    name: String,
+    old: usize,
+    new: usize,
+}
+
+/// Columns that are only in one of the files.
+#[derive(Debug, Default)]
+struct ColumnDiff<'a> {
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+}
+
+/// Compares two exports of the same table row by row, matching rows on `opts.keys`.
+/// Rows are compared on the columns both files have, the others are reported once
+/// in the summary. The old file is held in memory, the new one is streamed.
+pub fn process_csv_diff(
+    old: impl Read,
+    new: impl Read,
+    mut writer: impl Write,
+    opts: &DiffOpts,
+) -> Result<DiffStats> {
+    anyhow::ensure!(!opts.keys.is_empty(), "At least one key column is required");
+    let (mut old_rdr, old_headers) = build_csv_reader(old, &opts.reader)?;
+    let (mut new_rdr, new_headers) = build_csv_reader(new, &opts.reader)?;
+    let old_keys = key_columns(&old_headers, &opts.keys)?;
+    let new_keys = key_columns(&new_headers, &opts.keys)?;
+    let (columns, column_diff) = diff_columns(&old_headers, &new_headers, opts);
+
+    let mut old_rows = HashMap::new();
+    let mut old_order = Vec::new();
+    for record in old_rdr.records() {
+        let record = record?;
+        let key = row_key(&record, &old_keys);
+        if old_rows.contains_key(&key) {
+            anyhow::bail!(
+                "Duplicate key '{}' in the old file at line {}",
+                key_label(&key),
+                line(&record)
+            );
+        }
+        old_order.push(key.clone());
+        old_rows.insert(key, record);
+    }
+
+    let mut stats = DiffStats::default();
+    let mut changes = Vec::new();
+    let mut seen = HashSet::new();
+    for record in new_rdr.records() {
+        let record = record?;
+        let key = row_key(&record, &new_keys);
+        if !seen.insert(key.clone()) {
+            anyhow::bail!(
+                "Duplicate key '{}' in the new file at line {}",
+                key_label(&key),
+                line(&record)
+            );
+        }
+        let Some(old) = old_rows.get(&key) else {
+            stats.added += 1;
+            changes.push(Change::Added(
+                key_label(&key),
+                to_map(&new_headers, &record),
+            ));
+            continue;
+        };
+        let diff: Vec<ColumnChange> = columns
+            .iter()
+            .filter_map(|c| {
+                let old = old.get(c.old).unwrap_or_default();
+                let new = record.get(c.new).unwrap_or_default();
+                (old != new).then(|| ColumnChange {
+                    column: c.name.clone(),
+                    old: old.to_string(),
+                    new: new.to_string(),
+                })
+            })
+            .collect();
+        if diff.is_empty() {
+            stats.unchanged += 1;
+        } else {
+            stats.modified += 1;
+            changes.push(Change::Modified(key_label(&key), diff));
+        }
+    }
+    for key in old_order {
+        if !seen.contains(&key) {
+            stats.removed += 1;
+            let values = to_map(&old_headers, &old_rows[&key]);
+            changes.push(Change::Removed(key_label(&key), values));
+        }
+    }
+
+    match opts.format {
+        DiffFormat::Summary => write_summary(&mut writer, &column_diff, &changes, &stats, opts)?,
+        DiffFormat::Patch => {
+            let patch: Vec<Value> = changes.iter().flat_map(patch_ops).collect();
+            serde_json::to_writer_pretty(&mut writer, &patch)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(stats)
+}
+
+fn key_columns(headers: &StringRecord, keys: &[String]) -> Result<Vec<usize>> {
+    keys.iter()
+        .map(|k| {
+            headers
+                .iter()
+                .position(|h| h == k)
+                .ok_or_else(|| anyhow::anyhow!("Unknown key column: {}", k))
+        })
+        .collect()
+}
+
+/// Columns of both files in their old order, and those of only one, without keys
+/// and ignored columns.
+fn diff_columns<'a>(
+    old: &'a StringRecord,
+    new: &'a StringRecord,
+    opts: &DiffOpts,
+) -> (Vec<Column>, ColumnDiff<'a>) {
+    let compared = |name: &&str| !opts.keys.iter().chain(&opts.ignore).any(|c| c == name);
+    let mut columns = Vec::new();
+    let mut diff = ColumnDiff::default();
+    for (i, name) in old.iter().enumerate().filter(|(_, h)| compared(h)) {
+        match new.iter().position(|h| h == name) {
+            Some(j) => columns.push(Column {
+                name: name.to_string(),
+                old: i,
+                new: j,
+            }),
+            None => diff.removed.push(name),
+        }
+    }
+    diff.added = new
+        .iter()
+        .filter(|h| compared(h) && !old.iter().any(|o| o == *h))
+        .collect();
+    (columns, diff)
+}
+
+fn row_key(record: &StringRecord, key_idx: &[usize]) -> Vec<String> {
+    key_idx
+        .iter()
+        .map(|&i| record.get(i).unwrap_or_default().to_string())
+        .collect()
+}
+
+/// Joins key values with commas, quoting them as csv does when needed so that
+/// `("a,b", "c")` and `("a", "b,c")` stay apart.
+fn key_label(key: &[String]) -> String {
+    key.iter()
+        .map(|v| match v.contains([',', '"']) {
+            true => format!("\"{}\"", v.replace('"', "\"\"")),
+            false => v.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn line(record: &StringRecord) -> u64 {
+    record.position().map(|p| p.line()).unwrap_or_default()
+}
+
+fn to_map(headers: &StringRecord, record: &StringRecord) -> Map<String, Value> {
+    headers
+        .iter()
+        .zip(record.iter())
+        .map(|(h, v)| (h.to_string(), Value::String(v.to_string())))
+        .collect()
+}
+
+fn write_summary(
+    writer: &mut impl Write,
+    columns: &ColumnDiff,
+    changes: &[Change],
+    stats: &DiffStats,
+    opts: &DiffOpts,
+) -> Result<()> {
+    let paint = |color: &str, s: String| {
+        if opts.color {
+            format!("{}{}{}", color, s, RESET)
+        } else {
+            s
+        }
+    };
+    let key_name = opts.keys.join(",");
+    for column in &columns.added {
+        writeln!(writer, "{}", paint(GREEN, format!("+ column {}", column)))?;
+    }
+    for column in &columns.removed {
+        writeln!(writer, "{}", paint(RED, format!("- column {}", column)))?;
+    }
+    for change in changes {
+        match change {
+            Change::Added(key, _) => writeln!(
+                writer,
+                "{}",
+                paint(GREEN, format!("+ {}={}", key_name, key))
+            )?,
+            Change::Removed(key, _) => {
+                writeln!(writer, "{}", paint(RED, format!("- {}={}", key_name, key)))?
+            }
+            Change::Modified(key, diff) => {
+                writeln!(
+                    writer,
+                    "{}",
+                    paint(YELLOW, format!("~ {}={}", key_name, key))
+                )?;
+                for c in diff {
+                    writeln!(
+                        writer,
+                        "    {}: {} -> {}",
+                        c.column,
+                        paint(RED, format!("{:?}", c.old)),
+                        paint(GREEN, format!("{:?}", c.new))
+                    )?;
+                }
+            }
+        }
+    }
+    writeln!(
+        writer,
+        "{} added, {} removed, {} modified, {} unchanged",
+        stats.added, stats.removed, stats.modified, stats.unchanged
+    )?;
+    Ok(())
+}
+
+/// RFC 6902 operations against the table seen as an object of rows by key. Old values
+/// are carried by `test` operations in front of the changes.
+fn patch_ops(change: &Change) -> Vec<Value> {
+    match change {
+        Change::Added(key, values) => {
+            vec![json!({ "op": "add", "path": pointer(&[key]), "value": values })]
+        }
+        Change::Removed(key, values) => {
+            let path = pointer(&[key]);
+            vec![
+                json!({ "op": "test", "path": path, "value": values }),
+                json!({ "op": "remove", "path": path }),
+            ]
+        }
+        Change::Modified(key, diff) => diff
+            .iter()
+            .flat_map(|c| {
+                let path = pointer(&[key, &c.column]);
+                [
+                    json!({ "op": "test", "path": path, "value": c.old }),
+                    json!({ "op": "replace", "path": path, "value": c.new }),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn pointer(segments: &[&str]) -> String {
+    segments
+        .iter()
+        .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "Name,Position,Kit Number,Rating\n\
+                       Perin,Goalkeeper,37,6\n\
+                       Bonucci,Centre-Back,19,7\n\
+                       Khedira,Central Midfield,6,6\n";
+    const NEW: &str = "Name,Position,Kit Number,Rating,Team\n\
+                       Perin,Goalkeeper,1,7,Genoa\n\
+                       Bonucci,Centre-Back,19,8,\n\
+                       Ronaldo,Left Winger,7,9,\n";
+
+    fn diff(format: DiffFormat, ignore: &[&str]) -> Result<(String, DiffStats)> {
+        let opts = DiffOpts {
+            keys: vec!["Name".to_string()],
+            ignore: ignore.iter().map(|s| s.to_string()).collect(),
+            format,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        let stats = process_csv_diff(OLD.as_bytes(), NEW.as_bytes(), &mut buf, &opts)?;
+        Ok((String::from_utf8(buf)?, stats))
+    }
+
+    #[test]
+    fn test_process_csv_diff_summary() -> Result<()> {
+        let (ret, stats) = diff(DiffFormat::Summary, &["Rating"])?;
+        assert_eq!(
+            ret,
+            "+ column Team\n\
+             ~ Name=Perin\n    \
+             Kit Number: \"37\" -> \"1\"\n\
+             + Name=Ronaldo\n\
+             - Name=Khedira\n\
+             1 added, 1 removed, 1 modified, 1 unchanged\n"
+        );
+        assert_eq!(stats.modified, 1);
+        // a column only the old file has
+        let opts = DiffOpts {
+            keys: vec!["Name".to_string()],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        process_csv_diff(NEW.as_bytes(), OLD.as_bytes(), &mut buf, &opts)?;
+        let ret = String::from_utf8(buf)?;
+        assert!(ret.starts_with("- column Team\n~ Name=Perin\n"), "{}", ret);
+        assert!(!ret.contains("Team:"), "{}", ret);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_diff_patch() -> Result<()> {
+        let (ret, stats) = diff(DiffFormat::Patch, &["Rating", "Team"])?;
+        assert_eq!(
+            stats,
+            DiffStats {
+                added: 1,
+                removed: 1,
+                modified: 1,
+                unchanged: 1
+            }
+        );
+        let patch: Value = serde_json::from_str(&ret)?;
+        assert_eq!(
+            patch,
+            json!([
+                { "op": "test", "path": "/Perin/Kit Number", "value": "37" },
+                { "op": "replace", "path": "/Perin/Kit Number", "value": "1" },
+                { "op": "add", "path": "/Ronaldo", "value": {
+                    "Name": "Ronaldo", "Position": "Left Winger", "Kit Number": "7",
+                    "Rating": "9", "Team": ""
+                } },
+                { "op": "test", "path": "/Khedira", "value": {
+                    "Name": "Khedira", "Position": "Central Midfield", "Kit Number": "6",
+                    "Rating": "6"
+                } },
+                { "op": "remove", "path": "/Khedira" },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_diff_errors() {
+        let opts = DiffOpts {
+            keys: vec!["Position".to_string()],
+            ..Default::default()
+        };
+        let dup = "Name,Position\nPerin,Goalkeeper\nSzczesny,Goalkeeper\n";
+        let ret = process_csv_diff(dup.as_bytes(), NEW.as_bytes(), std::io::sink(), &opts);
+        assert_eq!(
+            ret.unwrap_err().to_string(),
+            "Duplicate key 'Goalkeeper' in the old file at line 3"
+        );
+        let opts = DiffOpts {
+            keys: vec!["Team".to_string()],
+            ..Default::default()
+        };
+        assert!(process_csv_diff(OLD.as_bytes(), NEW.as_bytes(), std::io::sink(), &opts).is_err());
+        assert_eq!(pointer(&["a/b", "c~d"]), "/a~1b/c~0d");
+    }
+
+    #[test]
+    fn test_process_csv_diff_composite_key_with_commas() -> Result<()> {
+        let opts = DiffOpts {
+            keys: vec!["Club".to_string(), "City".to_string()],
+            ..Default::default()
+        };
+        let old = "Club,City,Founded\n\"a,b\",c,1897\na,\"b,c\",1906\n";
+        let new = "Club,City,Founded\na,\"b,c\",1907\n\"a,b\",c,1897\n";
+        let mut buf = Vec::new();
+        let stats = process_csv_diff(old.as_bytes(), new.as_bytes(), &mut buf, &opts)?;
+        assert_eq!(stats.modified, 1);
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "~ Club,City=a,\"b,c\"\n    \
+             Founded: \"1906\" -> \"1907\"\n\
+             0 added, 0 removed, 1 modified, 1 unchanged\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_8};
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::{CsvReaderOpts, TextEncoding};
+
+const DECODE_BUF_SIZE: usize = 64 * 1024;
+
+/// Transcodes its input to UTF-8 on the fly, UTF-8 input is validated. Undecodable
+/// input fails the read with the csv row, line and byte offset of the offending
+/// sequence.
+pub struct DecodeReader<R> {
+    inner: BufReader<R>,
+    decoder: Decoder,
+    encoding: &'static Encoding,
+    out: Box<[u8]>,
+    start: usize,
+    end: usize,
+    offset: u64,
+    lines: u64,
+    rows: RowCounter,
+    done: bool,
+}
+
+/// Follows quoting in the decoded text to number rows as the csv crate does:
+/// the header is row 0, blank and comment lines are no rows.
+#[derive(Debug)]
+struct RowCounter {
+    quote: u8,
+    escape: Option<u8>,
+    comment: Option<u8>,
+    in_quotes: bool,
+    escaped: bool,
+    in_comment: bool,
+    in_row: bool,
+    rows: u64,
+}
+
+impl<R: Read> DecodeReader<R> {
+    /// Wraps `reader`, decoding it as `opts.encoding` or else UTF-8.
+    pub fn try_new(reader: R, opts: &CsvReaderOpts) -> io::Result<Self> {
+        let mut inner = BufReader::with_capacity(DECODE_BUF_SIZE, reader);
+        let encoding = match opts.encoding {
+            None => UTF_8,
+            Some(TextEncoding::Label(encoding)) => encoding,
+            Some(TextEncoding::Auto) => detect_encoding(inner.fill_buf()?),
+        };
+        Ok(Self {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            encoding,
+            out: vec![0; DECODE_BUF_SIZE].into(),
+            start: 0,
+            end: 0,
+            offset: 0,
+            lines: 0,
+            rows: RowCounter::new(opts),
+            done: false,
+        })
+    }
+}
+
+impl RowCounter {
+    fn new(opts: &CsvReaderOpts) -> Self {
+        Self {
+            quote: opts.quote,
+            escape: opts.escape,
+            comment: opts.comment,
+            in_quotes: false,
+            escaped: false,
+            in_comment: false,
+            in_row: false,
+            rows: 0,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.in_comment {
+                self.in_comment = b != b'\n';
+            } else if self.escaped {
+                self.escaped = false;
+            } else if self.in_quotes {
+                if Some(b) == self.escape {
+                    self.escaped = true;
+                } else if b == self.quote {
+                    self.in_quotes = false;
+                }
+            } else if b == b'\n' || b == b'\r' {
+                if self.in_row {
+                    self.rows += 1;
+                    self.in_row = false;
+                }
+            } else if !self.in_row && Some(b) == self.comment {
+                self.in_comment = true;
+            } else {
+                self.in_row = true;
+                // a doubled quote closes and reopens the quoted part
+                self.in_quotes = b == self.quote;
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = &mut self.decoder;
+        loop {
+            if self.start < self.end {
+                let n = buf.len().min(self.end - self.start);
+                buf[..n].copy_from_slice(&self.out[self.start..self.start + n]);
+                self.start += n;
+                return Ok(n);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            let src = self.inner.fill_buf()?;
+            let last = src.is_empty();
+            let (result, read, written) =
+                decoder.decode_to_utf8_without_replacement(src, &mut self.out, last);
+            self.inner.consume(read);
+            self.offset += read as u64;
+            self.lines += self.out[..written].iter().filter(|&&b| b == b'\n').count() as u64;
+            self.rows.feed(&self.out[..written]);
+            self.start = 0;
+            self.end = written;
+            match result {
+                DecoderResult::InputEmpty if last => self.done = true,
+                DecoderResult::InputEmpty | DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(bad, extra) => {
+                    // `read` covers the malformed bytes and the `extra` ones after them
+                    let offset = self.offset - bad as u64 - extra as u64;
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Invalid {} sequence in row {} (line {}, byte {})",
+                            self.encoding.name(),
+                            self.rows.rows,
+                            self.lines + 1,
+                            offset
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Picks the encoding from a BOM, keeps UTF-8 if `sample` is valid UTF-8 and
+/// guesses otherwise.
+fn detect_encoding(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+    match std::str::from_utf8(sample) {
+        // a multi byte character may be cut off at the end of the sample
+        Ok(_) => UTF_8,
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => {
+            let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+            detector.feed(sample, false);
+            detector.guess(None, Utf8Detection::Allow)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, UTF_16LE, WINDOWS_1252};
+
+    fn decode(data: &[u8], encoding: TextEncoding) -> io::Result<String> {
+        let opts = CsvReaderOpts {
+            encoding: Some(encoding),
+            ..Default::default()
+        };
+        let mut ret = String::new();
+        DecodeReader::try_new(data, &opts)?.read_to_string(&mut ret)?;
+        Ok(ret)
+    }
+
+    #[test]
+    fn test_decode_reader() -> io::Result<()> {
+        let latin1 = b"Name,City\nM\xfcller,K\xf6ln\n";
+        assert_eq!(
+            decode(latin1, TextEncoding::Label(WINDOWS_1252))?,
+            "Name,City\nMüller,Köln\n"
+        );
+        let utf16: Vec<u8> = "\u{feff}Name\nMüller\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(decode(&utf16, TextEncoding::Auto)?, "Name\nMüller\n");
+        assert_eq!(
+            decode(&utf16, TextEncoding::Label(UTF_16LE))?,
+            "Name\nMüller\n"
+        );
+        let (gbk, _, _) = GBK.encode("姓名\n张三\n");
+        assert_eq!(decode(&gbk, TextEncoding::Label(GBK))?, "姓名\n张三\n");
+        assert_eq!(decode("Müller".as_bytes(), TextEncoding::Auto)?, "Müller");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_reader_reports_row() {
+        let err = decode(b"Name\nok\nbad\xff\n", TextEncoding::Label(UTF_8)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "Invalid UTF-8 sequence in row 2 (line 3, byte 11)"
+        );
+        // quoted line breaks, blank and comment lines don't start rows
+        let opts = CsvReaderOpts {
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+        let data = b"Name,Bio\r\n\"Perin\",\"said \"\"hi\"\"\nthen left\"\r\n\n# note\nbad\xff,x\n";
+        let mut rdr = DecodeReader::try_new(&data[..], &opts).unwrap();
+        let err = rdr.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid UTF-8 sequence in row 2 (line 6, byte 54)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_without_encoding() {
+        let data = b"Name,Kit\nPerin,37\nSzcz\xeasny,1\n";
+        let err = crate::process_csv(
+            &data[..],
+            io::sink(),
+            crate::OutputFormat::Json,
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Invalid UTF-8 sequence in row 2 (line 3, byte 22)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(detect_encoding(b"\xef\xbb\xbfName"), UTF_8);
+        assert_eq!(detect_encoding(b"\xfe\xff\x00N"), encoding_rs::UTF_16BE);
+        assert_eq!(detect_encoding("Müll\u{e9}".as_bytes()), UTF_8);
+        let (gbk, _, _) = GBK.encode("姓名,城市\n张三,北京\n李四,上海\n王五,广州\n");
+        assert_eq!(detect_encoding(&gbk), GBK);
+    }
+}
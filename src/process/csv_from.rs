@@ -0,0 +1,155 @@
+use anyhow::Result;
+use csv::WriterBuilder;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Read, Write},
+};
+
+use crate::InputFormat;
+
+/// Converts records read as `format` into csv. The header is the union of all
+/// record keys in order of first appearance, nested objects are flattened with
+/// dotted keys and array items are addressed as `key[i]`.
+pub fn process_csv_from(
+    reader: impl Read,
+    writer: impl Write,
+    format: InputFormat,
+    delimiter: u8,
+) -> Result<()> {
+    let records = read_records(reader, format)?
+        .into_iter()
+        .map(|record| match record {
+            Value::Object(map) => Ok(flatten(&map)),
+            other => Err(anyhow::anyhow!("Expected an object record, got: {}", other)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut seen = HashSet::new();
+    let mut headers = Vec::new();
+    for key in records.iter().flat_map(|record| record.keys()) {
+        if seen.insert(key) {
+            headers.push(key);
+        }
+    }
+
+    let mut wtr = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    wtr.write_record(&headers)?;
+    for record in &records {
+        wtr.write_record(
+            headers
+                .iter()
+                .map(|&key| record.get(key).map(cell).unwrap_or_default()),
+        )?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+fn read_records(reader: impl Read, format: InputFormat) -> Result<Vec<Value>> {
+    let mut records = Vec::new();
+    let mut push = |value: Value| match value {
+        Value::Array(items) => records.extend(items),
+        other => records.push(other),
+    };
+    match format {
+        InputFormat::Json => push(serde_json::from_reader(reader)?),
+        InputFormat::Ndjson => {
+            for line in BufReader::new(reader).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+        InputFormat::Yaml => {
+            for doc in serde_yaml::Deserializer::from_reader(reader) {
+                match Value::deserialize(doc)? {
+                    Value::Null => {}
+                    value => push(value),
+                }
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn flatten(map: &Map<String, Value>) -> Map<String, Value> {
+    let mut ret = Map::new();
+    for (key, value) in map {
+        flatten_into(key.clone(), value, &mut ret);
+    }
+    ret
+}
+
+fn flatten_into(key: String, value: &Value, ret: &mut Map<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                flatten_into(format!("{}.{}", key, k), v, ret);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, v) in items.iter().enumerate() {
+                flatten_into(format!("{}[{}]", key, i), v, ret);
+            }
+        }
+        _ => {
+            ret.insert(key, value.clone());
+        }
+    }
+}
+
+pub fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(data: &str, format: InputFormat) -> Result<String> {
+        let mut buf = Vec::new();
+        process_csv_from(data.as_bytes(), &mut buf, format, b',')?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_process_csv_from_json() -> Result<()> {
+        let data = r#"[
+            { "Name": "Perin", "Kit": 37, "address": { "city": "Turin", "zip": "10121" } },
+            { "Name": "Szczesny, W", "Captain": true, "tags": ["gk", "pl"], "address": null }
+        ]"#;
+        assert_eq!(
+            convert(data, InputFormat::Json)?,
+            "Name,Kit,address.city,address.zip,Captain,tags[0],tags[1],address\n\
+             Perin,37,Turin,10121,,,,\n\
+             \"Szczesny, W\",,,,true,gk,pl,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_from_ndjson_and_yaml() -> Result<()> {
+        let expected = "Name,Kit\nPerin,37\nSzczesny,1\n";
+        let ndjson = "{\"Name\":\"Perin\",\"Kit\":37}\n\n{\"Name\":\"Szczesny\",\"Kit\":1}\n";
+        assert_eq!(convert(ndjson, InputFormat::Ndjson)?, expected);
+        let yaml = "- Name: Perin\n  Kit: 37\n- Name: Szczesny\n  Kit: 1\n";
+        assert_eq!(convert(yaml, InputFormat::Yaml)?, expected);
+        let yaml = "Name: Perin\nKit: 37\n---\nName: Szczesny\nKit: 1\n";
+        assert_eq!(convert(yaml, InputFormat::Yaml)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_from_rejects_scalars() {
+        assert!(convert("[1, 2]", InputFormat::Json).is_err());
+    }
+}
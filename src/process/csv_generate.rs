@@ -0,0 +1,262 @@
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_json::{Map, Number, Value};
+use std::{fmt, io::Write, str::FromStr};
+
+use crate::OutputFormat;
+
+use super::csv_writer::record_writer;
+
+const FIRST_NAMES: &[&str] = &[
+    "Aldo", "Alice", "Andrea", "Anna", "Beatrice", "Carlo", "Chiara", "Daniele", "Elena", "Emma",
+    "Federico", "Franca", "Gabriele", "Giorgia", "Giulia", "Leonardo", "Lorenzo", "Luca", "Marco",
+    "Maria", "Martina", "Matteo", "Mattia", "Paolo", "Riccardo", "Sara", "Simone", "Sofia",
+    "Tommaso", "Viola",
+];
+const LAST_NAMES: &[&str] = &[
+    "Bianchi", "Bruno", "Colombo", "Conti", "Costa", "De Luca", "Esposito", "Ferrara", "Ferrari",
+    "Fontana", "Gallo", "Giordano", "Greco", "Lombardi", "Mancini", "Marino", "Moretti", "Ricci",
+    "Rinaldi", "Rizzo", "Romano", "Rossi", "Russo", "Santoro", "Barbieri", "Caruso", "Galli",
+    "Leone", "Longo", "Martini",
+];
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+/// A generated column, parsed from `<name>=<kind>[:<args>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenColumn {
+    pub name: String,
+    pub kind: GenKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenKind {
+    Name,
+    Email,
+    Int(i64, i64),
+    Float(f64, f64),
+    Date(NaiveDate, NaiveDate),
+    Enum(Vec<String>),
+    Uuid,
+}
+
+/// Writes `rows` random records. The same `seed` always gives the same records;
+/// name and email columns of a row describe the same person.
+pub fn process_csv_generate(
+    writer: impl Write,
+    format: OutputFormat,
+    columns: &[GenColumn],
+    rows: usize,
+    seed: Option<u64>,
+) -> Result<()> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut writer = record_writer(writer, format);
+    for _ in 0..rows {
+        let first = *FIRST_NAMES.choose(&mut rng).expect("names are not empty");
+        let last = *LAST_NAMES.choose(&mut rng).expect("names are not empty");
+        let map: Map<String, Value> = columns
+            .iter()
+            .map(|c| (c.name.clone(), c.kind.generate(&mut rng, first, last)))
+            .collect();
+        writer.write_record(&Value::Object(map))?;
+    }
+    writer.finish()
+}
+
+impl GenKind {
+    fn generate(&self, rng: &mut StdRng, first: &str, last: &str) -> Value {
+        match self {
+            GenKind::Name => Value::String(format!("{} {}", first, last)),
+            GenKind::Email => {
+                let local = format!("{}.{}", first, last)
+                    .to_lowercase()
+                    .replace(' ', "");
+                let domain = EMAIL_DOMAINS.choose(rng).expect("domains are not empty");
+                Value::String(format!("{}{}@{}", local, rng.gen_range(1..100), domain))
+            }
+            GenKind::Int(min, max) => Value::Number(rng.gen_range(*min..=*max).into()),
+            GenKind::Float(min, max) => {
+                // two decimals read like amounts and prices, unless that leaves the range
+                let f = rng.gen_range(*min..=*max);
+                let rounded = (f * 100.0).round() / 100.0;
+                let f = match rounded.is_finite() && (*min..=*max).contains(&rounded) {
+                    true => rounded,
+                    false => f,
+                };
+                Number::from_f64(f).map_or(Value::Null, Value::Number)
+            }
+            GenKind::Date(start, end) => {
+                let days = rng.gen_range(0..=(*end - *start).num_days()) as u64;
+                let date = *start + Days::new(days);
+                Value::String(date.format("%Y-%m-%d").to_string())
+            }
+            GenKind::Enum(values) => Value::String(values.choose(rng).cloned().unwrap_or_default()),
+            GenKind::Uuid => {
+                let mut bytes: [u8; 16] = rng.gen();
+                // version 4, RFC 4122 variant
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                Value::String(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            }
+        }
+    }
+}
+
+impl FromStr for GenColumn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, spec) = s
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!("Invalid column spec: {}, expected <column>=<kind>", s)
+            })?;
+        let (kind, args) = match spec.split_once(':') {
+            Some((kind, args)) => (kind, Some(args)),
+            None => (spec, None),
+        };
+        let range = || {
+            args.and_then(|a| a.split_once(".."))
+                .ok_or_else(|| anyhow::anyhow!("{} needs a range, e.g. {}:1..100", kind, kind))
+        };
+        let kind = match (kind.to_lowercase().as_str(), args) {
+            ("name", None) => GenKind::Name,
+            ("email", None) => GenKind::Email,
+            ("uuid", None) => GenKind::Uuid,
+            ("int", _) => {
+                let (min, max) = range()?;
+                GenKind::Int(min.trim().parse()?, max.trim().parse()?)
+            }
+            ("float", _) => {
+                let (min, max) = range()?;
+                GenKind::Float(min.trim().parse()?, max.trim().parse()?)
+            }
+            ("date", _) => {
+                let (start, end) = range()?;
+                let parse = |s: &str| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d");
+                GenKind::Date(parse(start)?, parse(end)?)
+            }
+            ("enum", Some(values)) if !values.is_empty() => {
+                GenKind::Enum(values.split('|').map(String::from).collect())
+            }
+            _ => anyhow::bail!(
+                "Invalid column kind: {}, expected name, email, uuid, int:<min>..<max>, float:<min>..<max>, date:<start>..<end> or enum:<a>|<b>",
+                spec
+            ),
+        };
+        let empty = match &kind {
+            GenKind::Int(min, max) => min > max,
+            // gen_range panics when the width of the range overflows
+            GenKind::Float(min, max) => min > max || !(max - min).is_finite(),
+            GenKind::Date(start, end) => start > end,
+            _ => false,
+        };
+        anyhow::ensure!(!empty, "Empty range for column {}: {}", name, spec);
+        Ok(GenColumn {
+            name: name.to_string(),
+            kind,
+        })
+    }
+}
+
+impl fmt::Display for GenColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        match &self.kind {
+            GenKind::Name => write!(f, "name"),
+            GenKind::Email => write!(f, "email"),
+            GenKind::Uuid => write!(f, "uuid"),
+            GenKind::Int(min, max) => write!(f, "int:{}..{}", min, max),
+            GenKind::Float(min, max) => write!(f, "float:{}..{}", min, max),
+            GenKind::Date(start, end) => write!(f, "date:{}..{}", start, end),
+            GenKind::Enum(values) => write!(f, "enum:{}", values.join("|")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> Vec<GenColumn> {
+        [
+            "Id=uuid",
+            "Name=name",
+            "Email=email",
+            "Kit Number=int:1..99",
+            "Rating=float:5..10",
+            "DOB=date:1985-01-01..2005-12-31",
+            "Position=enum:Goalkeeper|Defender|Midfielder|Forward",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect()
+    }
+
+    fn generate(seed: Option<u64>) -> Result<String> {
+        let mut buf = Vec::new();
+        process_csv_generate(&mut buf, OutputFormat::Ndjson, &columns(), 50, seed)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_process_csv_generate() -> Result<()> {
+        let ret = generate(Some(42))?;
+        assert_eq!(ret, generate(Some(42))?);
+        assert_ne!(ret, generate(Some(43))?);
+        for line in ret.lines() {
+            let row: Value = serde_json::from_str(line)?;
+            let name = row["Name"].as_str().unwrap();
+            let (first, last) = name.split_once(' ').unwrap();
+            let email = row["Email"].as_str().unwrap();
+            assert!(email.starts_with(&first.to_lowercase()), "{}", email);
+            assert!(email.contains(&last.to_lowercase().replace(' ', "")));
+            assert!((1..=99).contains(&row["Kit Number"].as_i64().unwrap()));
+            assert!((5.0..=10.0).contains(&row["Rating"].as_f64().unwrap()));
+            let dob = row["DOB"].as_str().unwrap();
+            assert!(("1985-01-01"..="2005-12-31").contains(&dob));
+            let id = row["Id"].as_str().unwrap();
+            assert_eq!(id.len(), 36);
+            assert_eq!(&id[14..15], "4");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_float_keeps_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (min, max) in [(0.001, 0.004), (1e306, 1.7e308)] {
+            let kind = GenKind::Float(min, max);
+            for _ in 0..100 {
+                let f = kind.generate(&mut rng, "Gianluigi", "Buffon").as_f64();
+                assert!(f.is_some_and(|f| (min..=max).contains(&f)), "{:?}", f);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gen_column_from_str() {
+        for column in columns() {
+            assert_eq!(column.to_string().parse::<GenColumn>().unwrap(), column);
+        }
+        assert!("Age=int".parse::<GenColumn>().is_err());
+        assert!("Age=int:10..1".parse::<GenColumn>().is_err());
+        assert!("Amount=float:-1e308..1e308".parse::<GenColumn>().is_err());
+        assert!("Amount=float:0..inf".parse::<GenColumn>().is_err());
+        assert!("Team=enum:".parse::<GenColumn>().is_err());
+        assert!("Phone=phone".parse::<GenColumn>().is_err());
+        assert!("=name".parse::<GenColumn>().is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+};
+
+use crate::{CsvReaderOpts, JoinType, OutputFormat};
+
+use super::{build_csv_reader, csv_writer::record_writer};
+
+/// The rows of one input, indexed by join key. Keys keep their order of first appearance.
+#[derive(Debug, Default)]
+struct HashTable {
+    rows: HashMap<String, Vec<StringRecord>>,
+    keys: Vec<String>,
+}
+
+/// Joins `readers` on the `on` column. The input at `stream` is read row by row, all
+/// others are loaded into hash tables, so `stream` should point at the largest input.
+/// Non key columns that show up in several inputs get the input position as suffix,
+/// e.g. `Position_2`, repeated until the name is unused.
+pub fn process_csv_join<R: Read>(
+    readers: Vec<R>,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    on: &str,
+    how: JoinType,
+    stream: usize,
+) -> Result<()> {
+    anyhow::ensure!(readers.len() >= 2, "Joining needs at least two inputs");
+    anyhow::ensure!(
+        stream < readers.len(),
+        "Invalid input to stream: {}",
+        stream
+    );
+
+    let mut key_idx = Vec::new();
+    let mut headers = Vec::new();
+    let mut tables = Vec::new();
+    let mut streamed = None;
+    for (i, reader) in readers.into_iter().enumerate() {
+        let (mut rdr, header) = build_csv_reader(reader, opts)?;
+        let idx = header
+            .iter()
+            .position(|h| h == on)
+            .ok_or_else(|| anyhow::anyhow!("Unknown column: {} in input {}", on, i + 1))?;
+        let mut table = HashTable::default();
+        if i == stream {
+            streamed = Some(rdr);
+        } else {
+            for record in rdr.records() {
+                let record = record?;
+                let key = record.get(idx).unwrap_or_default().to_string();
+                table.insert(key, record);
+            }
+        }
+        key_idx.push(idx);
+        headers.push(header);
+        tables.push(table);
+    }
+    let columns = join_columns(&headers, &key_idx, on);
+
+    let mut record_writer = record_writer(writer, format);
+    let mut emit = |key: &str, rows: &[&[StringRecord]]| -> Result<()> {
+        let keep = match how {
+            JoinType::Inner => rows.iter().all(|r| !r.is_empty()),
+            JoinType::Left => !rows[0].is_empty(),
+            JoinType::Outer => true,
+        };
+        if !keep {
+            return Ok(());
+        }
+        for combination in product(rows) {
+            let map: Map<String, Value> = columns
+                .iter()
+                .map(|(name, input, col)| {
+                    let value = match (col, combination[*input]) {
+                        (None, _) => Value::String(key.to_string()),
+                        (Some(col), Some(record)) => {
+                            Value::String(record.get(*col).unwrap_or_default().to_string())
+                        }
+                        (Some(_), None) => Value::Null,
+                    };
+                    (name.clone(), value)
+                })
+                .collect();
+            record_writer.write_record(&Value::Object(map))?;
+        }
+        Ok(())
+    };
+
+    // keys of the hash tables that were already joined against a streamed row
+    let mut seen = HashSet::new();
+    let mut rdr = streamed.ok_or_else(|| anyhow::anyhow!("Missing input to stream"))?;
+    let mut record = StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let key = record.get(key_idx[stream]).unwrap_or_default();
+        let rows: Vec<&[StringRecord]> = tables
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                if i == stream {
+                    std::slice::from_ref(&record)
+                } else {
+                    t.get(key)
+                }
+            })
+            .collect();
+        if tables.iter().any(|t| !t.get(key).is_empty()) {
+            seen.insert(key.to_string());
+        }
+        emit(key, &rows)?;
+    }
+
+    // keys missing from the streamed input
+    let mut done = HashSet::new();
+    for key in tables.iter().flat_map(|t| &t.keys) {
+        if seen.contains(key) || !done.insert(key) {
+            continue;
+        }
+        let rows: Vec<&[StringRecord]> = tables.iter().map(|t| t.get(key)).collect();
+        emit(key, &rows)?;
+    }
+    record_writer.finish()
+}
+
+/// Unions the rows of `readers`, aligning columns by name. The header is the union
+/// of all headers in order of first appearance, missing fields are null.
+pub fn process_csv_concat<R: Read>(
+    readers: Vec<R>,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+) -> Result<()> {
+    let inputs = readers
+        .into_iter()
+        .map(|reader| build_csv_reader(reader, opts))
+        .collect::<Result<Vec<_>>>()?;
+    let mut seen = HashSet::new();
+    let mut columns = Vec::new();
+    for (_, header) in &inputs {
+        for name in header {
+            if seen.insert(name) {
+                columns.push(name.to_string());
+            }
+        }
+    }
+
+    let mut record_writer = record_writer(writer, format);
+    for (mut rdr, header) in inputs {
+        let positions: Vec<Option<usize>> = columns
+            .iter()
+            .map(|c| header.iter().position(|h| h == c))
+            .collect();
+        let mut record = StringRecord::new();
+        while rdr.read_record(&mut record)? {
+            let map: Map<String, Value> = columns
+                .iter()
+                .zip(&positions)
+                .map(|(name, pos)| {
+                    let value = pos
+                        .and_then(|i| record.get(i))
+                        .map(|s| Value::String(s.to_string()))
+                        .unwrap_or(Value::Null);
+                    (name.clone(), value)
+                })
+                .collect();
+            record_writer.write_record(&Value::Object(map))?;
+        }
+    }
+    record_writer.finish()
+}
+
+impl HashTable {
+    fn insert(&mut self, key: String, record: StringRecord) {
+        match self.rows.get_mut(&key) {
+            Some(rows) => rows.push(record),
+            None => {
+                self.keys.push(key.clone());
+                self.rows.insert(key, vec![record]);
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> &[StringRecord] {
+        self.rows.get(key).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Output columns as `(name, input, column)`, the key column comes first and only once.
+fn join_columns(
+    headers: &[StringRecord],
+    key_idx: &[usize],
+    on: &str,
+) -> Vec<(String, usize, Option<usize>)> {
+    let mut columns = vec![(on.to_string(), 0, None)];
+    let mut seen: HashSet<String> = HashSet::from([on.to_string()]);
+    for (i, header) in headers.iter().enumerate() {
+        for (j, name) in header.iter().enumerate() {
+            if j == key_idx[i] {
+                continue;
+            }
+            let mut name = name.to_string();
+            // the suffixed name may be taken too, e.g. by a `Position_2` column
+            while seen.contains(&name) {
+                name = format!("{}_{}", name, i + 1);
+            }
+            seen.insert(name.clone());
+            columns.push((name, i, Some(j)));
+        }
+    }
+    columns
+}
+
+/// Every combination of one row per input, a missing input contributes `None`.
+fn product<'a>(rows: &[&'a [StringRecord]]) -> Vec<Vec<Option<&'a StringRecord>>> {
+    let mut ret = vec![Vec::new()];
+    for input in rows {
+        let choices: Vec<Option<&StringRecord>> = if input.is_empty() {
+            vec![None]
+        } else {
+            input.iter().map(Some).collect()
+        };
+        ret = ret
+            .into_iter()
+            .flat_map(|prefix| {
+                choices.iter().map(move |choice| {
+                    let mut row = prefix.clone();
+                    row.push(*choice);
+                    row
+                })
+            })
+            .collect();
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYERS: &str =
+        "Name,Position\nPerin,Goalkeeper\nBonucci,Centre-Back\nDybala,Second Striker\n";
+    const KITS: &str = "Name,Kit Number,Position\nPerin,37,GK\nBonucci,19,CB\nRonaldo,7,CF\n";
+
+    fn join(how: JoinType, stream: usize) -> Result<String> {
+        let mut buf = Vec::new();
+        process_csv_join(
+            vec![PLAYERS.as_bytes(), KITS.as_bytes()],
+            &mut buf,
+            OutputFormat::Csv,
+            &Default::default(),
+            "Name",
+            how,
+            stream,
+        )?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_process_csv_join() -> Result<()> {
+        assert_eq!(
+            join(JoinType::Inner, 0)?,
+            "Name,Position,Kit Number,Position_2\n\
+             Perin,Goalkeeper,37,GK\n\
+             Bonucci,Centre-Back,19,CB\n"
+        );
+        assert_eq!(
+            join(JoinType::Left, 1)?,
+            "Name,Position,Kit Number,Position_2\n\
+             Perin,Goalkeeper,37,GK\n\
+             Bonucci,Centre-Back,19,CB\n\
+             Dybala,Second Striker,,\n"
+        );
+        assert_eq!(
+            join(JoinType::Outer, 0)?,
+            "Name,Position,Kit Number,Position_2\n\
+             Perin,Goalkeeper,37,GK\n\
+             Bonucci,Centre-Back,19,CB\n\
+             Dybala,Second Striker,,\n\
+             Ronaldo,,7,CF\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_join_duplicate_keys() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv_join(
+            vec![
+                "k,a\n1,x\n1,y\n".as_bytes(),
+                "k,b\n1,u\n1,v\n".as_bytes(),
+                "k,c\n1,z\n".as_bytes(),
+            ],
+            &mut buf,
+            OutputFormat::Ndjson,
+            &Default::default(),
+            "k",
+            JoinType::Inner,
+            2,
+        )?;
+        assert_eq!(String::from_utf8(buf)?.lines().count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_join_suffix_collision() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv_join(
+            vec![
+                "Name,Position,Position_2\nPerin,Goalkeeper,GK\n".as_bytes(),
+                KITS.as_bytes(),
+            ],
+            &mut buf,
+            OutputFormat::Csv,
+            &Default::default(),
+            "Name",
+            JoinType::Inner,
+            0,
+        )?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "Name,Position,Position_2,Kit Number,Position_2_2\n\
+             Perin,Goalkeeper,GK,37,GK\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_join_unknown_key() {
+        let mut buf = Vec::new();
+        let ret = process_csv_join(
+            vec![PLAYERS.as_bytes(), "Id\n1\n".as_bytes()],
+            &mut buf,
+            OutputFormat::Json,
+            &Default::default(),
+            "Name",
+            JoinType::Inner,
+            0,
+        );
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_process_csv_concat() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv_concat(
+            vec![
+                PLAYERS.as_bytes(),
+                "Name,Kit Number\nRonaldo,7\n".as_bytes(),
+            ],
+            &mut buf,
+            OutputFormat::Csv,
+            &Default::default(),
+        )?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "Name,Position,Kit Number\n\
+             Perin,Goalkeeper,\n\
+             Bonucci,Centre-Back,\n\
+             Dybala,Second Striker,\n\
+             Ronaldo,,7\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate};
+use csv::StringRecord;
+
+use crate::MaskRule;
+
+use super::text::Blake3;
+
+const REDACTED: &str = "REDACTED";
+// pseudonyms keep this many bytes of the keyed hash, hex encoded
+const PSEUDONYM_BYTES: usize = 8;
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%b %d, %Y", "%B %d, %Y", "%d %b %Y"];
+
+/// Scrubs sensitive columns of raw records before they are typed.
+pub struct Masker {
+    rules: Vec<(usize, String, MaskRule)>,
+    key: Option<Blake3>,
+}
+
+impl Masker {
+    pub fn try_new(
+        headers: &StringRecord,
+        rules: &[(String, MaskRule)],
+        key: Option<&[u8]>,
+    ) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|(name, rule)| {
+                let idx = headers
+                    .iter()
+                    .position(|h| h == name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown column: {}", name))?;
+                Ok((idx, name.clone(), *rule))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let key = match key {
+            Some(key) => Some(Blake3::try_new(key)?),
+            None if rules.iter().any(|(_, _, r)| *r == MaskRule::Hash) => {
+                anyhow::bail!("Hash masking needs a key, pass one with --mask-key")
+            }
+            None => None,
+        };
+        Ok(Self { rules, key })
+    }
+
+    pub fn mask(&self, record: &StringRecord) -> Result<StringRecord> {
+        let mut fields: Vec<String> = record.iter().map(String::from).collect();
+        for (idx, name, rule) in &self.rules {
+            let Some(field) = fields.get_mut(*idx) else {
+                continue;
+            };
+            // empty cells carry nothing to hide
+            if field.is_empty() {
+                continue;
+            }
+            *field = match rule {
+                MaskRule::Hash => self.pseudonym(field),
+                MaskRule::Redact => REDACTED.to_string(),
+                MaskRule::Partial => partial(field),
+                MaskRule::Year | MaskRule::Month => {
+                    generalize_date(field, *rule).ok_or_else(|| {
+                        let line = record.position().map(|p| p.line()).unwrap_or_default();
+                        anyhow::anyhow!(
+                            "Cannot read '{}' in column '{}' at line {} as a date",
+                            field,
+                            name,
+                            line
+                        )
+                    })?
+                }
+            };
+        }
+        let mut ret = StringRecord::from(fields);
+        ret.set_position(record.position().cloned());
+        Ok(ret)
+    }
+
+    /// Equal values map to equal pseudonyms under the same key, so masked files
+    /// can still be joined on the column.
+    fn pseudonym(&self, value: &str) -> String {
+        let key = self.key.as_ref().expect("checked in try_new");
+        key.keyed_hash(value.as_bytes())[..PSEUDONYM_BYTES]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Keeps the first and last character, and the domain of email addresses.
+fn partial(value: &str) -> String {
+    if let Some((local, domain)) = value.rsplit_once('@') {
+        return format!("{}@{}", partial(local), domain);
+    }
+    let chars: Vec<char> = value.chars().collect();
+    match chars.len() {
+        0..=2 => "*".repeat(chars.len()),
+        n => format!("{}{}{}", chars[0], "*".repeat(n - 2), chars[n - 1]),
+    }
+}
+
+/// Cuts a date down to its year or month. Trailing text such as an age is dropped.
+fn generalize_date(value: &str, rule: MaskRule) -> Option<String> {
+    let value = value.trim();
+    let date = DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.date_naive())
+        .ok()
+        .or_else(|| {
+            DATE_FORMATS
+                .iter()
+                .find_map(|f| NaiveDate::parse_and_remainder(value, f).ok())
+                .map(|(date, _)| date)
+        })?;
+    match rule {
+        MaskRule::Year => Some(date.year().to_string()),
+        _ => Some(format!("{}-{:02}", date.year(), date.month())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_csv, ColumnType, CsvConvertOpts, CsvQueryOpts, CsvSchema, OutputFormat};
+
+    const KEY: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
+
+    fn convert(data: &str, key: &[u8]) -> Result<String> {
+        let opts = CsvConvertOpts {
+            mask: vec![
+                ("Name".to_string(), MaskRule::Hash),
+                ("DOB".to_string(), MaskRule::Year),
+                ("Email".to_string(), MaskRule::Partial),
+                ("Phone".to_string(), MaskRule::Redact),
+            ],
+            mask_key: Some(key.to_vec()),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Csv, &opts)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_mask_is_deterministic() -> Result<()> {
+        let data = "Name,DOB,Email,Phone\n\
+                    Mattia Perin,\"Nov 10, 1992 (26)\",mattia@juventus.com,555-0101\n\
+                    Mattia Perin,1992-11-10,,\n";
+        let ret = convert(data, KEY)?;
+        assert_eq!(ret, convert(data, KEY)?);
+        let rows: Vec<&str> = ret.lines().collect();
+        let pseudonym = rows[1].split(',').next().unwrap();
+        assert_eq!(pseudonym.len(), PSEUDONYM_BYTES * 2);
+        assert_eq!(
+            rows[1],
+            format!("{},1992,m****a@juventus.com,REDACTED", pseudonym)
+        );
+        assert_eq!(rows[2], format!("{},1992,,", pseudonym));
+        let other = convert(data, b"fedcba9876543210fedcba9876543210")?;
+        assert!(!other.contains(pseudonym));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mask_errors() {
+        let headers = StringRecord::from(vec!["Name", "DOB"]);
+        let hash = [("Name".to_string(), MaskRule::Hash)];
+        assert!(Masker::try_new(&headers, &hash, None).is_err());
+        assert!(Masker::try_new(&headers, &hash, Some(b"short")).is_err());
+        let unknown = [("Email".to_string(), MaskRule::Partial)];
+        assert!(Masker::try_new(&headers, &unknown, None).is_err());
+        let opts = CsvConvertOpts {
+            column_types: vec![("DOB".to_string(), ColumnType::Date)],
+            mask: vec![("DOB".to_string(), MaskRule::Year)],
+            ..Default::default()
+        };
+        let err = process_csv(
+            "DOB\n1992-11-10\n".as_bytes(),
+            std::io::sink(),
+            OutputFormat::Csv,
+            &opts,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column 'DOB' is masked, so its type can't be set with --column-type"
+        );
+        let err = convert("Name,DOB,Email,Phone\nPerin,unknown,,\n", KEY).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot read 'unknown' in column 'DOB' at line 2 as a date"
+        );
+    }
+
+    #[test]
+    fn test_sort_by_masked_column() -> Result<()> {
+        let opts = CsvConvertOpts {
+            mask: vec![("Name".to_string(), MaskRule::Redact)],
+            query: CsvQueryOpts {
+                sort_by: Some("Name".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = "Name,Kit\nSzczesny,1\nBonucci,19\nPerin,37\n";
+        let mut buf = Vec::new();
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Csv, &opts)?;
+        // sorting on the plaintext would put Bonucci first and give it away
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "Name,Kit\nREDACTED,1\nREDACTED,19\nREDACTED,37\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_violations_hide_masked_values() -> Result<()> {
+        let schema =
+            "columns:\n  - name: Name\n    pattern: '[A-Z].*'\n  - name: Kit\n    max: 99\n";
+        let opts = CsvConvertOpts {
+            schema: Some(CsvSchema::try_new(schema.as_bytes())?),
+            mask: vec![("Name".to_string(), MaskRule::Redact)],
+            ..Default::default()
+        };
+        let data = "Name,Kit\nperin,100\n";
+        let report = process_csv(data.as_bytes(), std::io::sink(), OutputFormat::Csv, &opts)?;
+        let reasons: Vec<String> = report.violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "line 2, column 'Name': (masked) does not match pattern [A-Z].*",
+                "line 2, column 'Kit': 100 is greater than 99",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_and_generalize() {
+        assert_eq!(partial("Szczesny"), "S******y");
+        assert_eq!(partial("Li"), "**");
+        assert_eq!(partial("jo@x.it"), "**@x.it");
+        assert_eq!(
+            generalize_date("2019-05-01T10:00:00+02:00", MaskRule::Month),
+            Some("2019-05".to_string())
+        );
+        assert_eq!(
+            generalize_date("Apr 18, 1990 (29)", MaskRule::Year),
+            Some("1990".to_string())
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+const MAX_INDEX: usize = 9999;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Turns flat keys such as `address.city` and `tags[0]` into nested objects and
+/// arrays, the inverse of how `csv from` flattens records. Keys are split on
+/// `separator`, missing array items are filled with nulls. Indices are bounded
+/// by `MAX_INDEX`, so a stray header can't allocate a huge array.
+pub fn unflatten(value: Value, separator: &str) -> Result<Value> {
+    anyhow::ensure!(!separator.is_empty(), "The unflatten separator is empty");
+    let Value::Object(map) = value else {
+        return Ok(value);
+    };
+    let mut ret = Value::Object(Map::new());
+    for (key, value) in map {
+        let path = parse_path(&key, separator);
+        if let Some(i) = path.iter().find_map(|s| match s {
+            Segment::Index(i) if *i > MAX_INDEX => Some(*i),
+            _ => None,
+        }) {
+            anyhow::bail!(
+                "Cannot unflatten column '{}', index {} is larger than {}",
+                key,
+                i,
+                MAX_INDEX
+            );
+        }
+        insert(&mut ret, &path, value).map_err(|_| {
+            anyhow::anyhow!(
+                "Cannot unflatten column '{}', it conflicts with another column",
+                key
+            )
+        })?;
+    }
+    Ok(ret)
+}
+
+fn parse_path<'a>(key: &'a str, separator: &str) -> Vec<Segment<'a>> {
+    let mut path = Vec::new();
+    for part in key.split(separator) {
+        let mut name = part;
+        let mut indices = Vec::new();
+        while let Some(rest) = name.strip_suffix(']') {
+            let Some(open) = rest.rfind('[') else {
+                break;
+            };
+            let Ok(i) = rest[open + 1..].parse() else {
+                break;
+            };
+            indices.push(Segment::Index(i));
+            name = &rest[..open];
+        }
+        if !name.is_empty() || indices.is_empty() {
+            path.push(Segment::Key(name));
+        }
+        path.extend(indices.into_iter().rev());
+    }
+    path
+}
+
+fn insert(target: &mut Value, path: &[Segment], value: Value) -> Result<(), ()> {
+    let Some((segment, rest)) = path.split_first() else {
+        return match target {
+            Value::Null => {
+                *target = value;
+                Ok(())
+            }
+            // an empty `address` cell next to `address.city` doesn't clobber the object
+            _ if value.is_null() => Ok(()),
+            _ => Err(()),
+        };
+    };
+    let slot = match (segment, &mut *target) {
+        (Segment::Key(_), Value::Null) => {
+            *target = Value::Object(Map::new());
+            return insert(target, path, value);
+        }
+        (Segment::Index(_), Value::Null) => {
+            *target = Value::Array(Vec::new());
+            return insert(target, path, value);
+        }
+        (Segment::Key(k), Value::Object(map)) => map.entry(k.to_string()).or_insert(Value::Null),
+        (Segment::Index(i), Value::Array(items)) => {
+            if items.len() <= *i {
+                items.resize(i + 1, Value::Null);
+            }
+            &mut items[*i]
+        }
+        _ => return Err(()),
+    };
+    insert(slot, rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unflatten() -> Result<()> {
+        let value = json!({
+            "Name": "Perin",
+            "address.city": "Turin",
+            "address.zip": "10121",
+            "tags[1]": "it",
+            "tags[0]": "gk",
+            "matrix[0][1]": 2,
+            "contacts[0].email": "perin@example.com",
+        });
+        assert_eq!(
+            unflatten(value, ".")?,
+            json!({
+                "Name": "Perin",
+                "address": { "city": "Turin", "zip": "10121" },
+                "tags": ["gk", "it"],
+                "matrix": [[null, 2]],
+                "contacts": [{ "email": "perin@example.com" }],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unflatten_separator_and_conflicts() -> Result<()> {
+        let value = json!({ "address/city": "Turin", "a.b": 1, "x[y]": 2 });
+        assert_eq!(
+            unflatten(value, "/")?,
+            json!({ "address": { "city": "Turin" }, "a.b": 1, "x[y]": 2 })
+        );
+        assert_eq!(
+            unflatten(json!({ "address.city": "Turin", "address": null }), ".")?,
+            json!({ "address": { "city": "Turin" } })
+        );
+        assert!(unflatten(json!({ "address": "Turin", "address.city": "Turin" }), ".").is_err());
+        assert!(unflatten(json!({ "tags[0]": "gk", "tags.first": "gk" }), ".").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unflatten_index_bound() {
+        for key in [
+            "tags[18446744073709551615]",
+            "tags[4000000000]",
+            "tags[10000]",
+        ] {
+            let err = unflatten(json!({ "Name": "Perin", key: "gk" }), ".").unwrap_err();
+            assert!(err.to_string().contains("is larger than 9999"), "{}", err);
+        }
+        assert!(unflatten(json!({ "Name": "Perin", "tags[9999]": "gk" }), ".").is_ok());
+    }
+
+    #[test]
+    fn test_unflatten_sparse_indices() -> Result<()> {
+        assert_eq!(
+            unflatten(json!({ "tags[0]": "gk", "tags[5]": "captain" }), ".")?,
+            json!({ "tags": ["gk", null, null, null, null, "captain"] })
+        );
+        // e.g. after --select keeps a single item
+        assert_eq!(
+            unflatten(json!({ "tags[2]": "gk" }), ".")?,
+            json!({ "tags": [null, null, "gk"] })
+        );
+        Ok(())
+    }
+}
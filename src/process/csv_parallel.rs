@@ -0,0 +1,448 @@
+use anyhow::Result;
+use csv::{Position, StringRecord};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+
+use crate::{CsvReaderOpts, OutputFormat};
+
+use super::{
+    csv_process::{
+        csv_reader_builder, default_headers, open_csv_input, CsvConvertOpts, RecordPipeline,
+        RecordSink,
+    },
+    csv_schema::ValidationReport,
+    csv_writer::record_writer_with,
+};
+
+// converted rows take several times their csv size, keep the batches in flight small
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A run of complete records, `line` and `byte` locate its start in the input.
+#[derive(Debug)]
+struct Chunk {
+    data: Vec<u8>,
+    line: u64,
+    byte: u64,
+}
+
+#[derive(Debug, Default)]
+struct ChunkOutput {
+    rows: Vec<(Option<String>, Value)>,
+    report: ValidationReport,
+}
+
+/// Cuts the input into chunks of roughly `chunk_size` bytes that end on a record boundary.
+struct ChunkSplitter<R> {
+    reader: R,
+    chunk_size: usize,
+    scanner: Scanner,
+    carry: Vec<u8>,
+    eof: bool,
+    line: u64,
+    byte: u64,
+}
+
+/// Tracks just enough csv syntax to tell newlines that end a record from quoted ones.
+#[derive(Debug, Clone, Copy)]
+struct Scanner {
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    comment: Option<u8>,
+    state: ScanState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    RecordStart,
+    FieldStart,
+    Unquoted,
+    Quoted,
+    QuotedEscape,
+    QuoteInQuoted,
+    Comment,
+}
+
+/// Converts csv like [`super::process_csv`], but parses and converts chunks of the input
+/// on `opts.threads` workers. Records are written in their original order.
+pub fn process_csv_parallel(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvConvertOpts,
+) -> Result<ValidationReport> {
+    convert_parallel(reader, writer, format, opts, CHUNK_SIZE)
+}
+
+fn convert_parallel(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvConvertOpts,
+    chunk_size: usize,
+) -> Result<ValidationReport> {
+    let (reader, delimiter) = open_csv_input(reader, &opts.reader)?;
+    let mut splitter = ChunkSplitter::new(reader, chunk_size, delimiter, &opts.reader);
+    let mut first = splitter.next_chunk()?.unwrap_or(Chunk {
+        data: Vec::new(),
+        line: 1,
+        byte: 0,
+    });
+
+    let mut rdr = csv_reader_builder(&opts.reader, delimiter).from_reader(first.data.as_slice());
+    let headers = if opts.reader.header {
+        rdr.headers()?.clone()
+    } else {
+        default_headers(rdr.headers()?.len())
+    };
+    if opts.reader.header {
+        // the header is parsed here, workers only see records
+        let pos = rdr.position().clone();
+        first.data.drain(..pos.byte() as usize);
+        first.line = pos.line();
+        first.byte = pos.byte();
+    }
+
+    let mut pipeline = RecordPipeline::try_new(&headers, opts)?;
+    let mut queued = VecDeque::from([first]);
+    let mut sample = Vec::new();
+    // the leading chunks are sampled, parse errors are left for the workers to report
+    while sample.len() < pipeline.sample_rows() {
+        let mut builder = csv_reader_builder(&opts.reader, delimiter);
+        builder.has_headers(false).flexible(true);
+        let chunk = queued.back().expect("queued is not empty");
+        let wanted = pipeline.sample_rows() - sample.len();
+        sample.extend(
+            builder
+                .from_reader(chunk.data.as_slice())
+                .records()
+                .take(wanted)
+                .filter_map(Result::ok),
+        );
+        if sample.len() < pipeline.sample_rows() {
+            match splitter.next_chunk()? {
+                Some(chunk) => queued.push_back(chunk),
+                None => break,
+            }
+        }
+    }
+    pipeline.infer_types(&sample)?;
+    let columns = headers.len();
+    let mut sink = RecordSink::new(
+        record_writer_with(writer, format, &opts.columnar, opts.table_width),
+        &opts.query,
+    );
+    let mut report = ValidationReport::default();
+    let mut pending = Vec::new();
+    loop {
+        let mut batch = Vec::new();
+        while batch.len() < opts.threads.max(1) {
+            if !pipeline.validates() && sink.is_full() {
+                break;
+            }
+            let chunk = match queued.pop_front() {
+                Some(chunk) => chunk,
+                None => match splitter.next_chunk()? {
+                    Some(chunk) => chunk,
+                    None => break,
+                },
+            };
+            batch.push(chunk);
+        }
+        if batch.is_empty() && pending.is_empty() {
+            break;
+        }
+        pending = std::thread::scope(|s| -> Result<Vec<Result<ChunkOutput>>> {
+            let handles: Vec<_> = batch
+                .iter()
+                .map(|chunk| {
+                    let (pipeline, reader_opts) = (&pipeline, &opts.reader);
+                    s.spawn(move || convert_chunk(chunk, pipeline, reader_opts, delimiter, columns))
+                })
+                .collect();
+            // write out the previous batch while the workers are busy
+            for output in std::mem::take(&mut pending) {
+                let output: ChunkOutput = output?;
+                report.merge(output.report);
+                for (key, value) in output.rows {
+                    sink.push(key, value)?;
+                }
+            }
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .map_err(|_| anyhow::anyhow!("A csv conversion worker panicked"))
+                })
+                .collect()
+        })?;
+    }
+    sink.finish()?;
+    Ok(report)
+}
+
+fn convert_chunk(
+    chunk: &Chunk,
+    pipeline: &RecordPipeline,
+    opts: &CsvReaderOpts,
+    delimiter: u8,
+    columns: usize,
+) -> Result<ChunkOutput> {
+    let mut builder = csv_reader_builder(opts, delimiter);
+    builder.has_headers(false).flexible(true);
+    let mut rdr = builder.from_reader(chunk.data.as_slice());
+    let mut output = ChunkOutput::default();
+    let mut record = StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        // positions are relative to the chunk, make them point into the input
+        let mut pos = record.position().cloned().unwrap_or_else(Position::new);
+        pos.set_line(pos.line() + chunk.line - 1);
+        pos.set_byte(pos.byte() + chunk.byte);
+        if record.len() != columns {
+            anyhow::bail!(
+                "Found record with {} fields at line {}, but the header has {} fields",
+                record.len(),
+                pos.line(),
+                columns
+            );
+        }
+        record.set_position(Some(pos));
+        if let Some(row) = pipeline.convert(&record, &mut output.report)? {
+            output.rows.push(row);
+        }
+    }
+    Ok(output)
+}
+
+impl<R: Read> ChunkSplitter<R> {
+    fn new(reader: R, chunk_size: usize, delimiter: u8, opts: &CsvReaderOpts) -> Self {
+        Self {
+            reader,
+            chunk_size: chunk_size.max(1),
+            scanner: Scanner {
+                delimiter,
+                quote: opts.quote,
+                escape: opts.escape,
+                comment: opts.comment,
+                state: ScanState::RecordStart,
+            },
+            carry: Vec::new(),
+            eof: false,
+            line: 1,
+            byte: 0,
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<Option<Chunk>> {
+        let mut data = std::mem::take(&mut self.carry);
+        // chunks start on a record boundary
+        let mut scanner = Scanner {
+            state: ScanState::RecordStart,
+            ..self.scanner
+        };
+        let mut scanned = 0;
+        let mut boundary = None;
+        while !self.eof {
+            let n = (&mut self.reader)
+                .take(self.chunk_size as u64)
+                .read_to_end(&mut data)?;
+            self.eof = n < self.chunk_size;
+            if let Some(end) = scanner.scan(&data[scanned..]) {
+                boundary = Some(scanned + end);
+            }
+            scanned = data.len();
+            if boundary.is_some() {
+                break;
+            }
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+        if !self.eof {
+            self.carry = data.split_off(boundary.unwrap_or(data.len()));
+        }
+        let chunk = Chunk {
+            line: self.line,
+            byte: self.byte,
+            data,
+        };
+        self.line += chunk.data.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.byte += chunk.data.len() as u64;
+        Ok(Some(chunk))
+    }
+}
+
+impl Scanner {
+    /// Feeds `data` through the scanner, returning the end of the last complete record.
+    fn scan(&mut self, data: &[u8]) -> Option<usize> {
+        let mut boundary = None;
+        for (i, &b) in data.iter().enumerate() {
+            let prev = self.state;
+            self.state = match self.state {
+                ScanState::Comment if b == b'\n' => ScanState::RecordStart,
+                ScanState::Comment => ScanState::Comment,
+                ScanState::RecordStart if Some(b) == self.comment => ScanState::Comment,
+                ScanState::Quoted if Some(b) == self.escape => ScanState::QuotedEscape,
+                ScanState::Quoted if b == self.quote => ScanState::QuoteInQuoted,
+                ScanState::Quoted | ScanState::QuotedEscape => ScanState::Quoted,
+                ScanState::QuoteInQuoted if b == self.quote && self.escape.is_none() => {
+                    ScanState::Quoted
+                }
+                ScanState::RecordStart | ScanState::FieldStart if b == self.quote => {
+                    ScanState::Quoted
+                }
+                _ if b == b'\n' => ScanState::RecordStart,
+                _ if b == self.delimiter => ScanState::FieldStart,
+                _ => ScanState::Unquoted,
+            };
+            // comments and blank lines don't count, so every chunk holds a record
+            if self.state == ScanState::RecordStart
+                && !matches!(prev, ScanState::RecordStart | ScanState::Comment)
+            {
+                boundary = Some(i + 1);
+            }
+        }
+        boundary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::process_csv;
+    use crate::CsvQueryOpts;
+
+    const DATA: &str = "# leading comment\n\
+                        \n\
+                        Name,Position,Note\n\
+                        # a comment with a \"quote\n\
+                        Szczesny,Goalkeeper,\"line\nbreak\"\n\
+                        Perin,Goalkeeper,\"say \"\"hi\"\"\"\n\
+                        \n\
+                        Bonucci,Centre-Back,\n\
+                        Dybala,Second Striker,\"a,b\"\n";
+
+    fn opts(threads: usize) -> CsvConvertOpts {
+        let mut opts = CsvConvertOpts {
+            threads,
+            ..Default::default()
+        };
+        opts.reader.comment = Some(b'#');
+        opts
+    }
+
+    fn convert(opts: &CsvConvertOpts, chunk_size: usize) -> Result<String> {
+        let mut buf = Vec::new();
+        convert_parallel(
+            DATA.as_bytes(),
+            &mut buf,
+            OutputFormat::Ndjson,
+            opts,
+            chunk_size,
+        )?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_convert_parallel_matches_sequential() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv(DATA.as_bytes(), &mut buf, OutputFormat::Ndjson, &opts(1))?;
+        let expected = String::from_utf8(buf)?;
+        assert_eq!(expected.lines().count(), 4);
+        for chunk_size in 1..=DATA.len() + 1 {
+            assert_eq!(convert(&opts(3), chunk_size)?, expected, "{}", chunk_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_parallel_infers_from_leading_chunks() -> Result<()> {
+        let data = "Name,Kit Number\nSzczesny,1\nPerin,37\nBuffon,A\nDybala,10\n";
+        let opts = |threads| CsvConvertOpts {
+            threads,
+            infer_types: true,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Ndjson, &opts(1))?;
+        let expected = String::from_utf8(buf)?;
+        assert!(expected.contains("\"Kit Number\":\"1\""), "{}", expected);
+        for chunk_size in 1..=data.len() + 1 {
+            let mut buf = Vec::new();
+            convert_parallel(
+                data.as_bytes(),
+                &mut buf,
+                OutputFormat::Ndjson,
+                &opts(3),
+                chunk_size,
+            )?;
+            assert_eq!(String::from_utf8(buf)?, expected, "{}", chunk_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_parallel_query() -> Result<()> {
+        let opts = CsvConvertOpts {
+            query: CsvQueryOpts {
+                sort_by: Some("Name".to_string()),
+                limit: Some(2),
+                ..Default::default()
+            },
+            ..opts(2)
+        };
+        let ret = convert(&opts, 8)?;
+        let names: Vec<Value> = ret
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap()["Name"].clone())
+            .collect();
+        assert_eq!(names, ["Bonucci", "Dybala"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_parallel_reports_lines() -> Result<()> {
+        // the last record fails, so every chunk size has to carry line numbers over
+        let opts = |threads| CsvConvertOpts {
+            column_types: vec![("Position".to_string(), crate::ColumnType::Int)],
+            query: CsvQueryOpts {
+                filter: Some("Name == 'Dybala'".parse().unwrap()),
+                ..Default::default()
+            },
+            ..opts(threads)
+        };
+        let expected = process_csv(
+            DATA.as_bytes(),
+            std::io::sink(),
+            OutputFormat::Ndjson,
+            &opts(1),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(expected.contains("'Second Striker'"), "{}", expected);
+        for chunk_size in 1..=DATA.len() + 1 {
+            assert_eq!(
+                convert(&opts(3), chunk_size).unwrap_err().to_string(),
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scanner() {
+        let mut scanner = Scanner {
+            delimiter: b',',
+            quote: b'"',
+            escape: Some(b'\\'),
+            comment: None,
+            state: ScanState::RecordStart,
+        };
+        assert_eq!(scanner.scan(b"a,\"b\\\"\n"), None);
+        assert_eq!(scanner.scan(b"c\"\nd"), Some(3));
+        assert_eq!(scanner.state, ScanState::Unquoted);
+    }
+}
@@ -1,38 +1,634 @@
 use anyhow::Result;
-use csv::Reader;
-use serde::{Deserialize, Serialize};
+use csv::{Reader, ReaderBuilder, StringRecord};
 use serde_json::Value;
-use std::{fs, path::Path};
-
-use crate::cli::OutputFormat;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    name: String,
-    position: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8,
-}
-
-pub fn process_csv(input: &str, output: String, format: OutputFormat) -> Result<()> {
-    let mut rdr = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let headers = rdr.headers()?.clone();
-    for result in rdr.records() {
-        let record = result?;
-        let json_value = headers.iter().zip(record.iter()).collect::<Value>();
-        ret.push(json_value);
-    }
-
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read, Write},
+};
+
+use crate::{
+    cli::OutputFormat, ColumnType, ColumnarOpts, CsvQueryOpts, CsvReaderOpts, Delimiter, MaskRule,
+};
+
+use super::{
+    csv_encoding::DecodeReader,
+    csv_mask::Masker,
+    csv_nested::unflatten,
+    csv_parallel::process_csv_parallel,
+    csv_query::{compare_fields, select_columns, Filter},
+    csv_schema::{CsvSchema, SchemaValidator, ValidationReport},
+    csv_types::RecordTyper,
+    csv_writer::{record_writer_with, RecordWriter},
+};
+
+#[derive(Debug, Default)]
+pub struct CsvConvertOpts {
+    pub reader: CsvReaderOpts,
+    pub infer_types: bool,
+    pub column_types: Vec<(String, ColumnType)>,
+    pub schema: Option<CsvSchema>,
+    pub strict: bool,
+    pub query: CsvQueryOpts,
+    pub columnar: ColumnarOpts,
+    /// Applied after validation and filtering, which see the original values, and
+    /// before typing, so masked columns can't have a `column_types` entry
+    pub mask: Vec<(String, MaskRule)>,
+    /// Blake3 key for hash masking
+    pub mask_key: Option<Vec<u8>>,
+    /// Separator to build nested records from, e.g. `address.city`
+    pub unflatten: Option<String>,
+    /// Worker threads, fewer than two convert sequentially
+    pub threads: usize,
+    /// Columns to fit table output into, e.g. the terminal's
+    pub table_width: Option<usize>,
+}
+
+const SNIFF_CANDIDATES: &[u8] = b",\t;|";
+const SNIFF_BUF_SIZE: usize = 64 * 1024;
+
+/// Builds a csv reader honoring `opts`, together with the header row. Input is
+/// transcoded to UTF-8 first if an encoding is given. Headerless input gets
+/// `col_0`, `col_1`, ... as column names.
+pub fn build_csv_reader<R: Read>(
+    reader: R,
+    opts: &CsvReaderOpts,
+) -> Result<(Reader<BufReader<DecodeReader<R>>>, StringRecord)> {
+    let (reader, delimiter) = open_csv_input(reader, opts)?;
+    let mut rdr = csv_reader_builder(opts, delimiter).from_reader(reader);
+    let headers = if opts.header {
+        rdr.headers()?.clone()
+    } else {
+        default_headers(rdr.headers()?.len())
     };
-    println!("{}", output);
-    fs::write(Path::new("fixtures").join(output), content)?;
-    Ok(())
+    Ok((rdr, headers))
+}
+
+/// Wraps `reader` for csv parsing and resolves the delimiter, sniffing it if needed.
+pub fn open_csv_input<R: Read>(
+    reader: R,
+    opts: &CsvReaderOpts,
+) -> Result<(BufReader<DecodeReader<R>>, u8)> {
+    let reader = DecodeReader::try_new(reader, opts)?;
+    let mut reader = BufReader::with_capacity(SNIFF_BUF_SIZE, reader);
+    let delimiter = match opts.delimiter {
+        Delimiter::Char(c) => c,
+        Delimiter::Auto => sniff_delimiter(reader.fill_buf()?, opts.quote, opts.comment),
+    };
+    Ok((reader, delimiter))
+}
+
+pub fn csv_reader_builder(opts: &CsvReaderOpts, delimiter: u8) -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder
+        .delimiter(delimiter)
+        .has_headers(opts.header)
+        .quote(opts.quote)
+        .escape(opts.escape)
+        .double_quote(opts.escape.is_none())
+        .comment(opts.comment);
+    builder
+}
+
+pub fn default_headers(len: usize) -> StringRecord {
+    (0..len).map(|i| format!("col_{}", i)).collect()
+}
+
+/// Picks the candidate delimiter that splits the sampled lines into the same,
+/// largest number of fields. Falls back to `,`.
+fn sniff_delimiter(sample: &[u8], quote: u8, comment: Option<u8>) -> u8 {
+    let mut lines: Vec<&[u8]> = sample.split(|&b| b == b'\n').collect();
+    // the last line may be cut off by the sample boundary
+    if lines.len() > 1 {
+        lines.pop();
+    }
+    let lines: Vec<&[u8]> = lines
+        .into_iter()
+        .filter(|line| !line.is_empty() && line.first() != comment.as_ref())
+        .take(20)
+        .collect();
+    let mut best = (b',', 0);
+    for &candidate in SNIFF_CANDIDATES {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|line| count_unquoted(line, candidate, quote))
+            .collect();
+        let first = counts.first().copied().unwrap_or_default();
+        if first > best.1 && counts.iter().all(|&c| c == first) {
+            best = (candidate, first);
+        }
+    }
+    best.0
+}
+
+fn count_unquoted(line: &[u8], delimiter: u8, quote: u8) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+    for &b in line {
+        if b == quote {
+            quoted = !quoted;
+        } else if b == delimiter && !quoted {
+            count += 1;
+        }
+    }
+    count
+}
+
+pub fn process_csv(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvConvertOpts,
+) -> Result<ValidationReport> {
+    if opts.threads > 1 {
+        return process_csv_parallel(reader, writer, format, opts);
+    }
+    let (mut rdr, headers) = build_csv_reader(reader, &opts.reader)?;
+    let mut pipeline = RecordPipeline::try_new(&headers, opts)?;
+    let sample = rdr
+        .records()
+        .take(pipeline.sample_rows())
+        .collect::<Result<Vec<_>, _>>()?;
+    pipeline.infer_types(&sample)?;
+    let mut sink = RecordSink::new(
+        record_writer_with(writer, format, &opts.columnar, opts.table_width),
+        &opts.query,
+    );
+    let mut report = ValidationReport::default();
+    for record in &sample {
+        if let Some((key, value)) = pipeline.convert(record, &mut report)? {
+            sink.push(key, value)?;
+        }
+    }
+
+    // reuse a single record buffer so memory stays bounded by the widest row
+    let mut record = StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        // the report needs every row, everything else can stop at the limit
+        if !pipeline.validates() && sink.is_full() {
+            break;
+        }
+        if let Some((key, value)) = pipeline.convert(&record, &mut report)? {
+            sink.push(key, value)?;
+        }
+    }
+    sink.finish()?;
+    Ok(report)
+}
+
+/// Everything done to a single record: validation, filtering, typing and shaping.
+pub struct RecordPipeline<'a> {
+    opts: &'a CsvConvertOpts,
+    typer: RecordTyper,
+    validator: Option<SchemaValidator>,
+    filter: Option<Filter>,
+    masker: Option<Masker>,
+    sort_idx: Option<usize>,
+}
+
+/// Where converted records go: straight to the writer, or through sorting and tailing.
+pub struct RecordSink<'a> {
+    writer: Box<dyn RecordWriter + 'a>,
+    query: &'a CsvQueryOpts,
+    // sorting needs every matching row, everything else streams
+    sorted: Vec<(String, Value)>,
+    tail: VecDeque<Value>,
+    written: usize,
+}
+
+impl<'a> RecordPipeline<'a> {
+    pub fn try_new(headers: &StringRecord, opts: &'a CsvConvertOpts) -> Result<Self> {
+        let query = &opts.query;
+        for column in query.select.iter().chain(&query.sort_by) {
+            if !headers.iter().any(|h| h == column) {
+                anyhow::bail!("Unknown column: {}", column);
+            }
+        }
+        let validator = match &opts.schema {
+            Some(schema) => {
+                let masked: Vec<&str> = opts.mask.iter().map(|(c, _)| c.as_str()).collect();
+                Some(SchemaValidator::try_new(schema, headers, &masked)?)
+            }
+            None => None,
+        };
+        let filter = match &query.filter {
+            Some(expr) => Some(Filter::try_new(expr, headers)?),
+            None => None,
+        };
+        // a year or a pseudonym no longer parses as the column's original type
+        if let Some((name, _)) = opts
+            .mask
+            .iter()
+            .find(|(m, _)| opts.column_types.iter().any(|(c, _)| c == m))
+        {
+            anyhow::bail!(
+                "Column '{}' is masked, so its type can't be set with --column-type",
+                name
+            );
+        }
+        let masker = if opts.mask.is_empty() {
+            None
+        } else {
+            Some(Masker::try_new(
+                headers,
+                &opts.mask,
+                opts.mask_key.as_deref(),
+            )?)
+        };
+        Ok(Self {
+            opts,
+            typer: RecordTyper::try_new(headers, opts.infer_types, &opts.column_types)?,
+            validator,
+            filter,
+            masker,
+            sort_idx: query
+                .sort_by
+                .as_ref()
+                .and_then(|c| headers.iter().position(|h| h == c)),
+        })
+    }
+
+    pub fn validates(&self) -> bool {
+        self.validator.is_some()
+    }
+
+    /// How many leading records `infer_types` wants, none unless types are inferred.
+    pub fn sample_rows(&self) -> usize {
+        match self.typer.infers() {
+            true => self.opts.columnar.sample_rows,
+            false => 0,
+        }
+    }
+
+    /// Infers column types from the `sample` records, masked as they will be written.
+    pub fn infer_types(&mut self, sample: &[StringRecord]) -> Result<()> {
+        for record in sample {
+            match &self.masker {
+                Some(masker) => self.typer.sample(&masker.mask(record)?),
+                None => self.typer.sample(record),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sort key and value of `record`, or `None` if it is filtered out.
+    pub fn convert(
+        &self,
+        record: &StringRecord,
+        report: &mut ValidationReport,
+    ) -> Result<Option<(Option<String>, Value)>> {
+        if let Some(validator) = &self.validator {
+            let violations = validator.validate(record);
+            if self.opts.strict {
+                if let Some(v) = violations.first() {
+                    anyhow::bail!("Schema violation at {}", v);
+                }
+            }
+            report.add(violations);
+        }
+        if self.filter.as_ref().is_some_and(|f| !f.matches(record)) {
+            return Ok(None);
+        }
+        // everything past the filter sees masked values only, the sort key included
+        let masked = match &self.masker {
+            Some(masker) => Cow::Owned(masker.mask(record)?),
+            None => Cow::Borrowed(record),
+        };
+        let mut value = self.typer.to_value(&masked)?;
+        if !self.opts.query.select.is_empty() {
+            value = select_columns(value, &self.opts.query.select);
+        }
+        if let Some(separator) = &self.opts.unflatten {
+            value = unflatten(value, separator)?;
+        }
+        let key = self
+            .sort_idx
+            .map(|i| masked.get(i).unwrap_or_default().to_string());
+        Ok(Some((key, value)))
+    }
+}
+
+impl<'a> RecordSink<'a> {
+    pub fn new(writer: Box<dyn RecordWriter + 'a>, query: &'a CsvQueryOpts) -> Self {
+        Self {
+            writer,
+            query,
+            sorted: Vec::new(),
+            tail: VecDeque::new(),
+            written: 0,
+        }
+    }
+
+    /// Whether further records would be dropped anyway.
+    pub fn is_full(&self) -> bool {
+        self.query.sort_by.is_none()
+            && self.query.tail.is_none()
+            && self.written >= self.query.limit.unwrap_or(usize::MAX)
+    }
+
+    pub fn push(&mut self, key: Option<String>, value: Value) -> Result<()> {
+        match (key, self.query.tail) {
+            (Some(key), _) => self.sorted.push((key, value)),
+            // only the last rows are kept around
+            (None, Some(n)) => {
+                if self.tail.len() == n {
+                    self.tail.pop_front();
+                }
+                if n > 0 {
+                    self.tail.push_back(value);
+                }
+            }
+            (None, None) if !self.is_full() => {
+                self.writer.write_record(&value)?;
+                self.written += 1;
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.sorted.sort_by(|(a, _), (b, _)| compare_fields(a, b));
+        if self.query.desc {
+            self.sorted.reverse();
+        }
+        let skip = self
+            .query
+            .tail
+            .map_or(0, |n| self.sorted.len().saturating_sub(n));
+        let limit = self.query.limit.unwrap_or(usize::MAX);
+        for (_, value) in self.sorted.iter().skip(skip).take(limit) {
+            self.writer.write_record(value)?;
+        }
+        for value in &self.tail {
+            self.writer.write_record(value)?;
+        }
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "Name,Kit Number\nSzczesny,1\nPerin,37\n";
+
+    #[test]
+    fn test_process_csv_json() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv(
+            DATA.as_bytes(),
+            &mut buf,
+            OutputFormat::Json,
+            &Default::default(),
+        )?;
+        let expected = serde_json::to_string_pretty(&serde_json::json!([
+            { "Name": "Szczesny", "Kit Number": "1" },
+            { "Name": "Perin", "Kit Number": "37" },
+        ]))?;
+        assert_eq!(String::from_utf8(buf)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_yaml() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv(
+            DATA.as_bytes(),
+            &mut buf,
+            OutputFormat::Yaml,
+            &Default::default(),
+        )?;
+        let expected = serde_yaml::to_string(&serde_json::json!([
+            { "Name": "Szczesny", "Kit Number": "1" },
+            { "Name": "Perin", "Kit Number": "37" },
+        ]))?;
+        assert_eq!(String::from_utf8(buf)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_infer_types() -> Result<()> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOpts {
+            infer_types: true,
+            column_types: vec![("Name".to_string(), ColumnType::String)],
+            ..Default::default()
+        };
+        let data = "Name,Kit Number,Rating\n1,1,\nPerin,37,7.5\n";
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Json, &opts)?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            ret,
+            serde_json::json!([
+                { "Name": "1", "Kit Number": 1, "Rating": null },
+                { "Name": "Perin", "Kit Number": 37, "Rating": 7.5 },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_infer_types_per_column() -> Result<()> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOpts {
+            infer_types: true,
+            columnar: ColumnarOpts {
+                sample_rows: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = "Name,Kit Number,Rating\nSzczesny,1,7\nPerin,A,7.5\nBuffon,1,x\n";
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Json, &opts)?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            ret,
+            serde_json::json!([
+                { "Name": "Szczesny", "Kit Number": "1", "Rating": 7.0 },
+                { "Name": "Perin", "Kit Number": "A", "Rating": 7.5 },
+                { "Name": "Buffon", "Kit Number": "1", "Rating": "x" },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_column_type_error() {
+        let opts = CsvConvertOpts {
+            column_types: vec![("Kit Number".to_string(), ColumnType::Int)],
+            ..Default::default()
+        };
+        let data = "Name,Kit Number\nPerin,37\nSzczesny,one\n";
+        let err =
+            process_csv(data.as_bytes(), std::io::sink(), OutputFormat::Json, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid int value 'one' for column 'Kit Number' at line 3"
+        );
+    }
+
+    #[test]
+    fn test_process_csv_reader_opts() -> Result<()> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOpts {
+            reader: CsvReaderOpts {
+                delimiter: Delimiter::Auto,
+                header: false,
+                quote: b'\'',
+                comment: Some(b'#'),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = "# exported players\nSzczesny\t'Apr 18, 1990'\t1\nPerin\t'Nov 10, 1992'\t37\n";
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Json, &opts)?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            ret,
+            serde_json::json!([
+                { "col_0": "Szczesny", "col_1": "Apr 18, 1990", "col_2": "1" },
+                { "col_0": "Perin", "col_1": "Nov 10, 1992", "col_2": "37" },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sniff_delimiter() {
+        assert_eq!(sniff_delimiter(b"a,b,c\n1,2,3\n", b'"', None), b',');
+        assert_eq!(sniff_delimiter(b"a;b;c\n1;2,5;3\n", b'"', None), b';');
+        assert_eq!(sniff_delimiter(b"a|b\n\"1|2\"|3\n", b'"', None), b'|');
+        assert_eq!(sniff_delimiter(b"a\tb\n1\t2\n3\t", b'"', None), b'\t');
+        assert_eq!(sniff_delimiter(b"single column\n", b'"', None), b',');
+        assert_eq!(sniff_delimiter(b"# a,b\na;b\n", b'"', Some(b'#')), b';');
+    }
+
+    #[test]
+    fn test_process_csv_schema() -> Result<()> {
+        let schema = CsvSchema::try_new(
+            "columns:\n  - name: Kit Number\n    type: int\n    max: 99\n".as_bytes(),
+        )?;
+        let data = "Name,Kit Number\nPerin,37\nSzczesny,100\nBuffon,x\n";
+        let mut opts = CsvConvertOpts {
+            schema: Some(schema),
+            ..Default::default()
+        };
+        let report = process_csv(data.as_bytes(), std::io::sink(), OutputFormat::Json, &opts)?;
+        assert_eq!(report.rows, 3);
+        assert_eq!(report.invalid_rows, 2);
+        assert_eq!(
+            report.to_string(),
+            "line 3, column 'Kit Number': 100 is greater than 99\n\
+             line 4, column 'Kit Number': Invalid int value 'x'\n\
+             2 violations in 2 of 3 rows"
+        );
+
+        opts.strict = true;
+        let err =
+            process_csv(data.as_bytes(), std::io::sink(), OutputFormat::Json, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Schema violation at line 3, column 'Kit Number': 100 is greater than 99"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_query() -> Result<()> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOpts {
+            infer_types: true,
+            query: CsvQueryOpts {
+                select: vec!["Kit Number".to_string(), "Name".to_string()],
+                filter: Some("Position == 'Goalkeeper'".parse()?),
+                sort_by: Some("Kit Number".to_string()),
+                desc: true,
+                limit: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = "Name,Position,Kit Number\n\
+                    Szczesny,Goalkeeper,1\n\
+                    Perin,Goalkeeper,37\n\
+                    Bonucci,Centre-Back,19\n\
+                    Pinsoglio,Goalkeeper,31\n";
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Ndjson, &opts)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "{\"Kit Number\":37,\"Name\":\"Perin\"}\n{\"Kit Number\":31,\"Name\":\"Pinsoglio\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_sort_mixed_column() -> Result<()> {
+        let opts = CsvConvertOpts {
+            query: CsvQueryOpts {
+                select: vec!["Kit".to_string()],
+                sort_by: Some("Kit".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let data = "Kit\n1a\n10\nnan\n9\n-inf\n2.5\nb\n";
+        let mut buf = Vec::new();
+        process_csv(data.as_bytes(), &mut buf, OutputFormat::Csv, &opts)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "Kit\n2.5\n9\n10\n-inf\n1a\nb\nnan\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_limit_streams() -> Result<()> {
+        let mut buf = Vec::new();
+        let opts = CsvConvertOpts {
+            query: CsvQueryOpts {
+                limit: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        process_csv(DATA.as_bytes(), &mut buf, OutputFormat::Ndjson, &opts)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "{\"Name\":\"Szczesny\",\"Kit Number\":\"1\"}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_tail() -> Result<()> {
+        for (sort_by, expected) in [(None, "Perin"), (Some("Name"), "Szczesny")] {
+            let mut buf = Vec::new();
+            let opts = CsvConvertOpts {
+                query: CsvQueryOpts {
+                    tail: Some(1),
+                    sort_by: sort_by.map(String::from),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            process_csv(DATA.as_bytes(), &mut buf, OutputFormat::Ndjson, &opts)?;
+            let ret: Value = serde_json::from_slice(&buf)?;
+            assert_eq!(ret["Name"], expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_empty() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv(
+            "Name\n".as_bytes(),
+            &mut buf,
+            OutputFormat::Json,
+            &Default::default(),
+        )?;
+        assert_eq!(String::from_utf8(buf)?, "[]");
+        Ok(())
+    }
 }
@@ -0,0 +1,352 @@
+use anyhow::Result;
+use csv::StringRecord;
+use serde_json::{Map, Value};
+use std::{cmp::Ordering, collections::HashMap, str::FromStr};
+
+/// A boolean filter over csv fields, e.g. `Position == 'Goalkeeper' && "Kit Number" < 10`.
+///
+/// Columns are bare words or double quoted, strings are single quoted. Both sides of a
+/// comparison are compared as numbers when they both parse as one, as strings otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Compare(Operand, CmpOp, Operand),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Column(String),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(String),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+/// An [`Expr`] bound to the columns of a csv header.
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+    columns: HashMap<String, usize>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(t) => Err(anyhow::anyhow!("Unexpected token in filter: {:?}", t)),
+        }
+    }
+}
+
+impl Filter {
+    pub fn try_new(expr: &Expr, headers: &StringRecord) -> Result<Self> {
+        let columns: HashMap<String, usize> = headers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.to_string(), i))
+            .collect();
+        expr.check_columns(&columns)?;
+        Ok(Self {
+            expr: expr.clone(),
+            columns,
+        })
+    }
+
+    pub fn matches(&self, record: &StringRecord) -> bool {
+        self.eval(&self.expr, record)
+    }
+
+    fn eval(&self, expr: &Expr, record: &StringRecord) -> bool {
+        match expr {
+            Expr::Compare(l, op, r) => {
+                let ord = compare_fields(self.resolve(l, record), self.resolve(r, record));
+                match op {
+                    CmpOp::Eq => ord == Ordering::Equal,
+                    CmpOp::Ne => ord != Ordering::Equal,
+                    CmpOp::Lt => ord == Ordering::Less,
+                    CmpOp::Le => ord != Ordering::Greater,
+                    CmpOp::Gt => ord == Ordering::Greater,
+                    CmpOp::Ge => ord != Ordering::Less,
+                }
+            }
+            Expr::And(l, r) => self.eval(l, record) && self.eval(r, record),
+            Expr::Or(l, r) => self.eval(l, record) || self.eval(r, record),
+            Expr::Not(e) => !self.eval(e, record),
+        }
+    }
+
+    fn resolve<'a>(&self, operand: &'a Operand, record: &'a StringRecord) -> &'a str {
+        match operand {
+            Operand::Literal(s) => s,
+            Operand::Column(name) => self
+                .columns
+                .get(name)
+                .and_then(|&i| record.get(i))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Expr {
+    fn check_columns(&self, columns: &HashMap<String, usize>) -> Result<()> {
+        match self {
+            Expr::Compare(l, _, r) => {
+                for operand in [l, r] {
+                    if let Operand::Column(name) = operand {
+                        if !columns.contains_key(name) {
+                            anyhow::bail!("Unknown column in filter: {}", name);
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Expr::And(l, r) | Expr::Or(l, r) => {
+                l.check_columns(columns)?;
+                r.check_columns(columns)
+            }
+            Expr::Not(e) => e.check_columns(columns),
+        }
+    }
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(anyhow::anyhow!("Missing closing parenthesis in filter")),
+                }
+            }
+            _ => {
+                let left = self.parse_operand()?;
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    t => anyhow::bail!("Expected a comparison operator in filter, got {:?}", t),
+                };
+                Ok(Expr::Compare(left, op, self.parse_operand()?))
+            }
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Operand::Column(name)),
+            Some(Token::Str(s)) | Some(Token::Num(s)) => Ok(Operand::Literal(s)),
+            t => Err(anyhow::anyhow!(
+                "Expected a column or value in filter, got {:?}",
+                t
+            )),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+        let (token, len) = match (c, two.as_str()) {
+            (_, "==") => (Token::Op(CmpOp::Eq), 2),
+            (_, "!=") => (Token::Op(CmpOp::Ne), 2),
+            (_, "<=") => (Token::Op(CmpOp::Le), 2),
+            (_, ">=") => (Token::Op(CmpOp::Ge), 2),
+            (_, "&&") => (Token::And, 2),
+            (_, "||") => (Token::Or, 2),
+            ('<', _) => (Token::Op(CmpOp::Lt), 1),
+            ('>', _) => (Token::Op(CmpOp::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('\'', _) | ('"', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&x| x == c)
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated quote in filter: {}", s))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+                let token = if c == '"' {
+                    Token::Ident(text)
+                } else {
+                    Token::Str(text)
+                };
+                (token, end + 2)
+            }
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            _ if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') => {
+                let len = chars[i..]
+                    .iter()
+                    .position(|&x| !(x.is_alphanumeric() || matches!(x, '_' | '-' | '.')))
+                    .unwrap_or(chars.len() - i);
+                let word: String = chars[i..i + len].iter().collect();
+                let token = match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ if word.parse::<f64>().is_ok() => Token::Num(word),
+                    _ => Token::Ident(word),
+                };
+                (token, len)
+            }
+            _ => anyhow::bail!("Unexpected character in filter: {}", c),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// A total order over fields: finite numbers first, compared numerically, then
+/// everything else (including "nan" and "inf") compared lexically.
+pub fn compare_fields(a: &str, b: &str) -> Ordering {
+    let number = |s: &str| s.trim().parse::<f64>().ok().filter(|f| f.is_finite());
+    match (number(a), number(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Keeps only the `columns` of `value`, in the given order.
+pub fn select_columns(value: Value, columns: &[String]) -> Value {
+    match value {
+        Value::Object(mut map) => Value::Object(
+            columns
+                .iter()
+                .filter_map(|c| map.remove(c).map(|v| (c.clone(), v)))
+                .collect::<Map<_, _>>(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, record: &[&str]) -> Result<bool> {
+        let headers = StringRecord::from(vec!["Name", "Position", "Kit Number"]);
+        let filter = Filter::try_new(&filter.parse()?, &headers)?;
+        Ok(filter.matches(&StringRecord::from(record.to_vec())))
+    }
+
+    #[test]
+    fn test_filter() -> Result<()> {
+        let perin = ["Perin", "Goalkeeper", "37"];
+        assert!(matches("Position == 'Goalkeeper'", &perin)?);
+        assert!(!matches("Position != 'Goalkeeper'", &perin)?);
+        assert!(matches("\"Kit Number\" > 9", &perin)?);
+        assert!(matches("\"Kit Number\" >= 37 and Name < 'Q'", &perin)?);
+        assert!(matches(
+            "!(Name == 'Buffon') && (\"Kit Number\" < 10 || Name == 'Perin')",
+            &perin
+        )?);
+        assert!(!matches(
+            "not Name == 'Perin' or Position == 'Centre-Back'",
+            &perin
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_errors() {
+        assert!(matches("Age > 3", &[]).is_err());
+        assert!(matches("Name == 'Perin", &[]).is_err());
+        assert!(matches("Name 'Perin'", &[]).is_err());
+        assert!(matches("(Name == 'Perin'", &[]).is_err());
+        assert!(matches("Name == 'Perin' Position", &[]).is_err());
+    }
+
+    #[test]
+    fn test_compare_fields() {
+        assert_eq!(compare_fields("9", "10"), Ordering::Less);
+        assert_eq!(compare_fields("9", "10a"), Ordering::Less);
+        assert_eq!(compare_fields("1.0", "1"), Ordering::Equal);
+        assert_eq!(compare_fields("nan", "1"), Ordering::Greater);
+        assert_eq!(compare_fields("inf", "nan"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_select_columns() {
+        let value = serde_json::json!({ "Name": "Perin", "Position": "Goalkeeper", "Kit": 37 });
+        let columns = vec!["Kit".to_string(), "Name".to_string()];
+        let ret = select_columns(value, &columns);
+        assert_eq!(
+            serde_json::to_string(&ret).unwrap(),
+            r#"{"Kit":37,"Name":"Perin"}"#
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::Result;
+use csv::StringRecord;
+use regex::Regex;
+use serde::Deserialize;
+use std::{fmt, io::Read};
+
+use crate::ColumnType;
+
+use super::csv_types::{convert_value, parse_float};
+
+/// Keep at most this many violations around, the rest are only counted.
+const MAX_REPORTED_VIOLATIONS: usize = 1000;
+
+/// A column spec file, e.g.
+///
+/// ```yaml
+/// columns:
+///   - name: Kit Number
+///     type: int
+///     required: true
+///     min: 1
+///     max: 99
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvSchema {
+    pub columns: Vec<ColumnSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Option<ColumnType>,
+    #[serde(default)]
+    pub required: bool,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<String>>,
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub line: u64,
+    pub column: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub invalid_rows: usize,
+    pub total_violations: usize,
+    pub violations: Vec<Violation>,
+}
+
+pub struct SchemaValidator {
+    columns: Vec<(usize, ColumnSpec, Option<Regex>)>,
+    // masked columns, whose values stay out of the violations
+    masked: Vec<bool>,
+}
+
+impl CsvSchema {
+    /// Loads a schema from yaml or json.
+    pub fn try_new(reader: impl Read) -> Result<Self> {
+        Ok(serde_yaml::from_reader(reader)?)
+    }
+}
+
+impl SchemaValidator {
+    pub fn try_new(schema: &CsvSchema, headers: &StringRecord, masked: &[&str]) -> Result<Self> {
+        let columns: Vec<(usize, ColumnSpec, Option<Regex>)> = schema
+            .columns
+            .iter()
+            .map(|spec| {
+                let idx = headers
+                    .iter()
+                    .position(|h| h == spec.name)
+                    .ok_or_else(|| anyhow::anyhow!("Schema column not in header: {}", spec.name))?;
+                let pattern = spec
+                    .pattern
+                    .as_ref()
+                    .map(|p| Regex::new(&format!("^(?:{})$", p)))
+                    .transpose()?;
+                Ok((idx, spec.clone(), pattern))
+            })
+            .collect::<Result<_>>()?;
+        let masked = columns
+            .iter()
+            .map(|(_, spec, _)| masked.contains(&spec.name.as_str()))
+            .collect();
+        Ok(Self { columns, masked })
+    }
+
+    /// Checks `record` against every column spec, returning one violation per failed column.
+    pub fn validate(&self, record: &StringRecord) -> Vec<Violation> {
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        self.columns
+            .iter()
+            .zip(&self.masked)
+            .filter_map(|((idx, spec, pattern), masked)| {
+                let field = record.get(*idx).unwrap_or_default();
+                check_field(field, spec, pattern.as_ref(), *masked).map(|reason| Violation {
+                    line,
+                    column: spec.name.clone(),
+                    reason,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ValidationReport {
+    pub fn add(&mut self, violations: Vec<Violation>) {
+        self.rows += 1;
+        if violations.is_empty() {
+            return;
+        }
+        self.invalid_rows += 1;
+        self.total_violations += violations.len();
+        let room = MAX_REPORTED_VIOLATIONS.saturating_sub(self.violations.len());
+        self.violations.extend(violations.into_iter().take(room));
+    }
+
+    /// Adds the rows and violations of a report covering later rows.
+    pub fn merge(&mut self, other: ValidationReport) {
+        self.rows += other.rows;
+        self.invalid_rows += other.invalid_rows;
+        self.total_violations += other.total_violations;
+        let room = MAX_REPORTED_VIOLATIONS.saturating_sub(self.violations.len());
+        self.violations
+            .extend(other.violations.into_iter().take(room));
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column '{}': {}",
+            self.line, self.column, self.reason
+        )
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in &self.violations {
+            writeln!(f, "{}", v)?;
+        }
+        if self.total_violations > self.violations.len() {
+            writeln!(
+                f,
+                "... {} more violations",
+                self.total_violations - self.violations.len()
+            )?;
+        }
+        write!(
+            f,
+            "{} violations in {} of {} rows",
+            self.total_violations, self.invalid_rows, self.rows
+        )
+    }
+}
+
+/// Values of `masked` columns are left out of the returned reason.
+fn check_field(
+    field: &str,
+    spec: &ColumnSpec,
+    pattern: Option<&Regex>,
+    masked: bool,
+) -> Option<String> {
+    if field.is_empty() {
+        return spec.required.then(|| "missing required value".to_string());
+    }
+    let shown = |value: String| match masked {
+        true => "(masked)".to_string(),
+        false => value,
+    };
+    if let Some(ty) = spec.ty {
+        if convert_value(field, ty).is_err() {
+            return Some(format!(
+                "Invalid {} value {}",
+                ty,
+                shown(format!("'{}'", field))
+            ));
+        }
+    }
+    if spec.min.is_some() || spec.max.is_some() {
+        // NaN would pass both bounds
+        let Some(n) = parse_float(field.trim()) else {
+            return Some(format!(
+                "Invalid {} value {}",
+                ColumnType::Float,
+                shown(format!("'{}'", field))
+            ));
+        };
+        if let Some(min) = spec.min.filter(|&min| n < min) {
+            return Some(format!("{} is less than {}", shown(n.to_string()), min));
+        }
+        if let Some(max) = spec.max.filter(|&max| n > max) {
+            return Some(format!("{} is greater than {}", shown(n.to_string()), max));
+        }
+    }
+    let len = field.chars().count();
+    if let Some(min) = spec.min_length.filter(|&min| len < min) {
+        return Some(format!("length {} is shorter than {}", len, min));
+    }
+    if let Some(max) = spec.max_length.filter(|&max| len > max) {
+        return Some(format!("length {} is longer than {}", len, max));
+    }
+    if let Some(allowed) = &spec.allowed {
+        if !allowed.iter().any(|a| a == field) {
+            return Some(format!(
+                "{} is not an allowed value",
+                shown(format!("'{}'", field))
+            ));
+        }
+    }
+    if let Some(pattern) = pattern {
+        if !pattern.is_match(field) {
+            return Some(format!(
+                "{} does not match pattern {}",
+                shown(format!("'{}'", field)),
+                spec.pattern.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = include_str!("../../assets/juventus.schema.yaml");
+
+    #[test]
+    fn test_schema_validator() -> Result<()> {
+        let schema = CsvSchema::try_new(SCHEMA.as_bytes())?;
+        let mut rdr = csv::Reader::from_reader(
+            "Name,Position,DOB,Nationality,Kit Number\n\
+             Perin,Goalkeeper,\"Nov 10, 1992 (26)\",Italy,37\n\
+             ,Coach,1992-11-10,Italy,100\n"
+                .as_bytes(),
+        );
+        let validator = SchemaValidator::try_new(&schema, rdr.headers()?, &[])?;
+        let mut report = ValidationReport::default();
+        for record in rdr.records() {
+            report.add(validator.validate(&record?));
+        }
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.invalid_rows, 1);
+        let reasons: Vec<String> = report.violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "line 3, column 'Name': missing required value",
+                "line 3, column 'Position': 'Coach' is not an allowed value",
+                "line 3, column 'DOB': '1992-11-10' does not match pattern [A-Z][a-z]{2} \\d{1,2}, \\d{4} \\(\\d+\\)",
+                "line 3, column 'Kit Number': 100 is greater than 99",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_validator_unknown_column() -> Result<()> {
+        let schema = CsvSchema::try_new("columns:\n  - name: Age\n".as_bytes())?;
+        let headers = StringRecord::from(vec!["Name"]);
+        assert!(SchemaValidator::try_new(&schema, &headers, &[]).is_err());
+        assert!(CsvSchema::try_new("columns:\n  - name: Age\n    typo: 1\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_field_type() {
+        let spec: ColumnSpec = serde_yaml::from_str("name: Kit\ntype: int").unwrap();
+        assert_eq!(check_field("7", &spec, None, false), None);
+        assert_eq!(check_field("", &spec, None, false), None);
+        assert_eq!(
+            check_field("seven", &spec, None, false),
+            Some("Invalid int value 'seven'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_field_bounds() {
+        let spec: ColumnSpec = serde_yaml::from_str(
+            "name: Kit
+min: 1
+max: 99",
+        )
+        .unwrap();
+        assert_eq!(check_field("7.5", &spec, None, false), None);
+        assert_eq!(
+            check_field("0", &spec, None, false),
+            Some("0 is less than 1".to_string())
+        );
+        for field in ["NaN", "inf", "-infinity", "1e999", "seven"] {
+            assert_eq!(
+                check_field(field, &spec, None, false),
+                Some(format!("Invalid float value '{}'", field))
+            );
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::Result;
+use calamine::{open_workbook_auto_from_rs, Data, Range, Reader};
+use csv::{Position, StringRecord};
+use std::{
+    io::{Cursor, Read, Write},
+    str::FromStr,
+};
+
+use crate::cli::OutputFormat;
+
+use super::{
+    csv_process::{default_headers, CsvConvertOpts, RecordPipeline, RecordSink},
+    csv_schema::ValidationReport,
+    csv_writer::record_writer_with,
+};
+
+/// Which part of a workbook to convert.
+#[derive(Debug, Default, Clone)]
+pub struct SheetOpts {
+    /// Sheet name, or its zero-based index; the first sheet if omitted
+    pub sheet: Option<String>,
+    pub range: Option<CellRange>,
+}
+
+/// A rectangular cell range such as `B3:F20`, as zero-based (row, column) corners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRange {
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+}
+
+/// Converts a sheet of an xlsx, xls, xlsb or ods workbook like a csv file: the first
+/// row of the range holds the headers and cells are fed to the csv pipeline as text.
+pub fn process_sheet(
+    mut reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvConvertOpts,
+    sheet: &SheetOpts,
+) -> Result<ValidationReport> {
+    // workbooks are zip or ole archives, which need to seek
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(buf))?;
+    let names = workbook.sheet_names();
+    let name = match &sheet.sheet {
+        None => names.first(),
+        Some(s) => names
+            .iter()
+            .find(|n| *n == s)
+            .or_else(|| s.parse::<usize>().ok().and_then(|i| names.get(i))),
+    }
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "Sheet not found: {}, available: {}",
+            sheet.sheet.as_deref().unwrap_or("0"),
+            names.join(", ")
+        )
+    })?
+    .clone();
+    let mut range = workbook.worksheet_range(&name)?;
+    if let Some(r) = sheet.range {
+        // calamine allocates the whole requested rectangle, so keep to the used cells
+        range = match (range.start(), range.end()) {
+            (Some(start), Some(end)) => {
+                let start = (r.start.0.max(start.0), r.start.1.max(start.1));
+                let end = (r.end.0.min(end.0), r.end.1.min(end.1));
+                match start.0 <= end.0 && start.1 <= end.1 {
+                    true => range.range(start, end),
+                    false => Range::empty(),
+                }
+            }
+            _ => Range::empty(),
+        };
+    }
+    let first_row = range.start().map_or(0, |(row, _)| row as u64);
+
+    let mut rows = records(&range).enumerate().filter_map(|(i, mut record)| {
+        // blank rows are skipped like blank lines in csv input
+        if record.iter().all(str::is_empty) {
+            return None;
+        }
+        let mut pos = Position::new();
+        pos.set_line(first_row + i as u64 + 1);
+        record.set_position(Some(pos));
+        Some(record)
+    });
+    let headers = if opts.reader.header {
+        let row = rows.next().unwrap_or_default();
+        row.iter()
+            .enumerate()
+            .map(|(i, h)| match h {
+                "" => format!("col_{}", i),
+                h => h.to_string(),
+            })
+            .collect()
+    } else {
+        default_headers(range.width())
+    };
+
+    let mut pipeline = RecordPipeline::try_new(&headers, opts)?;
+    let sample: Vec<StringRecord> = rows.by_ref().take(pipeline.sample_rows()).collect();
+    pipeline.infer_types(&sample)?;
+    let mut sink = RecordSink::new(
+        record_writer_with(writer, format, &opts.columnar, opts.table_width),
+        &opts.query,
+    );
+    let mut report = ValidationReport::default();
+    for record in sample.into_iter().chain(rows) {
+        if !pipeline.validates() && sink.is_full() {
+            break;
+        }
+        if let Some((key, value)) = pipeline.convert(&record, &mut report)? {
+            sink.push(key, value)?;
+        }
+    }
+    sink.finish()?;
+    Ok(report)
+}
+
+fn records(range: &Range<Data>) -> impl Iterator<Item = StringRecord> + '_ {
+    range
+        .rows()
+        .map(|row| row.iter().map(cell_text).collect::<StringRecord>())
+}
+
+/// Dates are written as ISO 8601, everything else as excel displays it by default.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(dt) if dt.is_datetime() => {
+            let (y, mo, d, h, mi, s, _) = dt.to_ymd_hms_milli();
+            if (h, mi, s) == (0, 0, 0) {
+                format!("{:04}-{:02}-{:02}", y, mo, d)
+            } else {
+                format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", y, mo, d, h, mi, s)
+            }
+        }
+        cell => cell.to_string(),
+    }
+}
+
+impl FromStr for CellRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Invalid cell range {:?}, expected e.g. A1:D20", s))?;
+        let (start, end) = (parse_cell(start)?, parse_cell(end)?);
+        anyhow::ensure!(
+            start.0 <= end.0 && start.1 <= end.1,
+            "Invalid cell range {:?}, the first cell must be the top left one",
+            s
+        );
+        Ok(Self { start, end })
+    }
+}
+
+/// Parses an `A1` style reference into a zero-based (row, column) pair.
+fn parse_cell(s: &str) -> Result<(u32, u32)> {
+    let s = s.trim().to_ascii_uppercase();
+    let split = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let (letters, digits) = s.split_at(split);
+    let column = letters.bytes().try_fold(0u32, |acc, b| {
+        acc.checked_mul(26)?.checked_add((b - b'A' + 1) as u32)
+    });
+    match (column, digits.parse::<u32>()) {
+        (Some(column), Ok(row)) if column > 0 && row > 0 => Ok((row - 1, column - 1)),
+        _ => anyhow::bail!("Invalid cell reference: {:?}", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_csv;
+
+    fn convert(data: &[u8], opts: &CsvConvertOpts, sheet: &SheetOpts) -> Result<String> {
+        let mut buf = Vec::new();
+        process_sheet(data, &mut buf, OutputFormat::Ndjson, opts, sheet)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_process_sheet_matches_csv() -> Result<()> {
+        let opts = CsvConvertOpts {
+            infer_types: true,
+            ..Default::default()
+        };
+        let mut expected = Vec::new();
+        process_csv(
+            include_bytes!("../../assets/juventus.csv").as_slice(),
+            &mut expected,
+            OutputFormat::Ndjson,
+            &opts,
+        )?;
+        let expected = String::from_utf8(expected)?;
+        let xlsx = include_bytes!("../../assets/juventus.xlsx");
+        let ods = include_bytes!("../../assets/juventus.ods");
+        assert_eq!(convert(xlsx, &opts, &SheetOpts::default())?, expected);
+        assert_eq!(convert(ods, &opts, &SheetOpts::default())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_sheet_select_range() -> Result<()> {
+        let xlsx = include_bytes!("../../assets/juventus.xlsx");
+        let opts = CsvConvertOpts::default();
+        let sheet = SheetOpts {
+            sheet: Some("Clubs".to_string()),
+            range: Some("A3:B4".parse()?),
+        };
+        assert_eq!(
+            convert(xlsx, &opts, &sheet)?,
+            "{\"Club\":\"Juventus\",\"Founded\":\"1897\"}\n"
+        );
+        let sheet = SheetOpts {
+            sheet: Some("1".to_string()),
+            range: Some("A1:B1".parse()?),
+        };
+        assert_eq!(convert(xlsx, &opts, &sheet)?, "");
+        let sheet = SheetOpts {
+            sheet: Some("Teams".to_string()),
+            range: None,
+        };
+        assert_eq!(
+            convert(xlsx, &opts, &sheet).unwrap_err().to_string(),
+            "Sheet not found: Teams, available: Players, Clubs"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_sheet_range_beyond_data() -> Result<()> {
+        let xlsx = include_bytes!("../../assets/juventus.xlsx");
+        let opts = CsvConvertOpts::default();
+        let expected = convert(xlsx, &opts, &SheetOpts::default())?;
+        for range in ["A1:XFD1048576", "A1:ZZ2000000"] {
+            let sheet = SheetOpts {
+                sheet: None,
+                range: Some(range.parse()?),
+            };
+            assert_eq!(convert(xlsx, &opts, &sheet)?, expected);
+        }
+        let sheet = SheetOpts {
+            sheet: None,
+            range: Some("ZZ100:ZZ2000000".parse()?),
+        };
+        assert_eq!(convert(xlsx, &opts, &sheet)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cell_range() {
+        let range: CellRange = "b3:AA20".parse().unwrap();
+        assert_eq!(range.start, (2, 1));
+        assert_eq!(range.end, (19, 26));
+        assert!("A1".parse::<CellRange>().is_err());
+        assert!("A0:B2".parse::<CellRange>().is_err());
+        assert!("C3:A1".parse::<CellRange>().is_err());
+    }
+}
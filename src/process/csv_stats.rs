@@ -0,0 +1,377 @@
+use anyhow::Result;
+use serde_json::{json, Map, Number, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use crate::{ColumnType, CsvReaderOpts, OutputFormat};
+
+use super::{
+    build_csv_reader,
+    csv_query::compare_fields,
+    csv_types::{infer_type, infer_value},
+    csv_writer::record_writer,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// An aggregate over a group, written as `count`, `sum(Kit Number)`, `avg(Kit Number)`, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub func: AggFunc,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    count: usize,
+    nulls: usize,
+    freq: HashMap<String, usize>,
+    // dropped as soon as a non numeric value shows up
+    numbers: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct AggState {
+    count: usize,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+pub fn process_csv_stats(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    top: usize,
+) -> Result<()> {
+    let (mut rdr, headers) = build_csv_reader(reader, opts)?;
+    let mut stats: Vec<ColumnStats> = headers
+        .iter()
+        .map(|_| ColumnStats {
+            numbers: Some(Vec::new()),
+            ..Default::default()
+        })
+        .collect();
+    for record in rdr.records() {
+        let record = record?;
+        for (s, field) in stats.iter_mut().zip(record.iter()) {
+            s.add(field);
+        }
+    }
+
+    let mut record_writer = record_writer(writer, format);
+    for (name, s) in headers.iter().zip(stats) {
+        record_writer.write_record(&s.into_value(name, top))?;
+    }
+    record_writer.finish()
+}
+
+pub fn process_csv_group_by(
+    reader: impl Read,
+    writer: impl Write,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    group_by: &str,
+    aggregates: &[Aggregate],
+) -> Result<()> {
+    let (mut rdr, headers) = build_csv_reader(reader, opts)?;
+    let position = |column: &str| {
+        headers
+            .iter()
+            .position(|h| h == column)
+            .ok_or_else(|| anyhow::anyhow!("Unknown column: {}", column))
+    };
+    let key_idx = position(group_by)?;
+    let agg_idx = aggregates
+        .iter()
+        .map(|agg| agg.column.as_deref().map(position).transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    // groups keep the order in which their keys first show up
+    let mut index = HashMap::new();
+    let mut groups: Vec<(String, Vec<AggState>)> = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let key = record.get(key_idx).unwrap_or_default();
+        let i = *index.entry(key.to_string()).or_insert_with(|| {
+            groups.push((key.to_string(), vec![AggState::default(); aggregates.len()]));
+            groups.len() - 1
+        });
+        for (state, idx) in groups[i].1.iter_mut().zip(&agg_idx) {
+            let Some(idx) = idx else {
+                state.count += 1;
+                continue;
+            };
+            let field = record.get(*idx).unwrap_or_default().trim();
+            if field.is_empty() {
+                continue;
+            }
+            let n = field.parse::<f64>().map_err(|_| {
+                let line = record.position().map(|p| p.line()).unwrap_or_default();
+                anyhow::anyhow!(
+                    "Non numeric value '{}' in column '{}' at line {}",
+                    field,
+                    headers.get(*idx).unwrap_or_default(),
+                    line
+                )
+            })?;
+            state.add(n);
+        }
+    }
+
+    let mut record_writer = record_writer(writer, format);
+    for (key, states) in groups {
+        let mut map = Map::new();
+        map.insert(group_by.to_string(), Value::String(key));
+        for (agg, state) in aggregates.iter().zip(states) {
+            map.insert(agg.to_string(), state.value(agg.func));
+        }
+        record_writer.write_record(&Value::Object(map))?;
+    }
+    record_writer.finish()
+}
+
+impl ColumnStats {
+    fn add(&mut self, field: &str) {
+        self.count += 1;
+        if field.is_empty() {
+            self.nulls += 1;
+            return;
+        }
+        if let Some(numbers) = &mut self.numbers {
+            match infer_type(field) {
+                Some(ColumnType::Int) | Some(ColumnType::Float) => {
+                    numbers.push(field.parse().unwrap_or_default())
+                }
+                _ => self.numbers = None,
+            }
+        }
+        *self.freq.entry(field.to_string()).or_default() += 1;
+    }
+
+    fn into_value(self, name: &str, top: usize) -> Value {
+        let numeric = self.numbers.as_ref().is_some_and(|n| !n.is_empty());
+        let cmp = |a: &&String, b: &&String| {
+            if numeric {
+                compare_fields(a, b)
+            } else {
+                a.cmp(b)
+            }
+        };
+        let min = self.freq.keys().min_by(cmp);
+        let max = self.freq.keys().max_by(cmp);
+        let to_value = |s: Option<&String>| match s {
+            Some(s) if numeric => infer_value(s),
+            Some(s) => Value::String(s.clone()),
+            None => Value::Null,
+        };
+
+        let mut map = Map::new();
+        map.insert("column".to_string(), json!(name));
+        map.insert("count".to_string(), json!(self.count));
+        map.insert("nulls".to_string(), json!(self.nulls));
+        map.insert("distinct".to_string(), json!(self.freq.len()));
+        map.insert("min".to_string(), to_value(min));
+        map.insert("max".to_string(), to_value(max));
+        match self.numbers {
+            Some(mut numbers) if numeric => {
+                let n = numbers.len() as f64;
+                let mean = numbers.iter().sum::<f64>() / n;
+                let variance = if numbers.len() > 1 {
+                    numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)
+                } else {
+                    0.0
+                };
+                numbers.sort_by(|a, b| a.total_cmp(b));
+                let mid = numbers.len() / 2;
+                let median = if numbers.len() % 2 == 0 {
+                    (numbers[mid - 1] + numbers[mid]) / 2.0
+                } else {
+                    numbers[mid]
+                };
+                map.insert("mean".to_string(), float(mean));
+                map.insert("median".to_string(), float(median));
+                map.insert("stddev".to_string(), float(variance.sqrt()));
+            }
+            _ => {
+                let mut freq: Vec<(String, usize)> = self.freq.into_iter().collect();
+                freq.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+                let top: Vec<Value> = freq
+                    .into_iter()
+                    .take(top)
+                    .map(|(value, count)| json!({ "value": value, "count": count }))
+                    .collect();
+                map.insert("top".to_string(), Value::Array(top));
+            }
+        }
+        Value::Object(map)
+    }
+}
+
+impl AggState {
+    fn add(&mut self, n: f64) {
+        self.count += 1;
+        self.sum += n;
+        self.min = Some(self.min.map_or(n, |m| m.min(n)));
+        self.max = Some(self.max.map_or(n, |m| m.max(n)));
+    }
+
+    fn value(&self, func: AggFunc) -> Value {
+        match func {
+            AggFunc::Count => json!(self.count),
+            AggFunc::Sum => float(self.sum),
+            AggFunc::Avg if self.count == 0 => Value::Null,
+            AggFunc::Avg => float(self.sum / self.count as f64),
+            AggFunc::Min => self.min.map(float).unwrap_or(Value::Null),
+            AggFunc::Max => self.max.map(float).unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Whole floats are emitted as integers, so `sum(Kit Number)` reads `42` rather than `42.0`.
+fn float(f: f64) -> Value {
+    if f.fract() == 0.0 && f.abs() < i64::MAX as f64 {
+        json!(f as i64)
+    } else {
+        Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (func, column) = match s.split_once('(') {
+            Some((func, rest)) => {
+                let column = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow::anyhow!("Missing closing parenthesis: {}", s))?;
+                (func.trim(), Some(column.trim().to_string()))
+            }
+            None => (s, None),
+        };
+        let func = match func.to_lowercase().as_str() {
+            "count" => AggFunc::Count,
+            "sum" => AggFunc::Sum,
+            "avg" | "mean" => AggFunc::Avg,
+            "min" => AggFunc::Min,
+            "max" => AggFunc::Max,
+            _ => anyhow::bail!("Invalid aggregate function: {}", func),
+        };
+        match (func, column) {
+            (AggFunc::Count, _) => Ok(Aggregate { func, column: None }),
+            (_, Some(column)) if !column.is_empty() => Ok(Aggregate {
+                func,
+                column: Some(column),
+            }),
+            _ => Err(anyhow::anyhow!("Aggregate needs a column: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let func = match self.func {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Avg => "avg",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+        };
+        match &self.column {
+            Some(column) => write!(f, "{}({})", func, column),
+            None => write!(f, "{}", func),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "Name,Nationality,Kit Number\n\
+                        Szczesny,Poland,1\n\
+                        Perin,Italy,37\n\
+                        Bonucci,Italy,19\n\
+                        Pinsoglio,Italy,\n";
+
+    #[test]
+    fn test_process_csv_stats() -> Result<()> {
+        let mut buf = Vec::new();
+        process_csv_stats(
+            DATA.as_bytes(),
+            &mut buf,
+            OutputFormat::Json,
+            &Default::default(),
+            1,
+        )?;
+        let ret: Value = serde_json::from_slice(&buf)?;
+        assert_eq!(
+            ret[1],
+            json!({
+                "column": "Nationality", "count": 4, "nulls": 0, "distinct": 2,
+                "min": "Italy", "max": "Poland", "top": [{ "value": "Italy", "count": 3 }]
+            })
+        );
+        assert_eq!(
+            ret[2],
+            json!({
+                "column": "Kit Number", "count": 4, "nulls": 1, "distinct": 3,
+                "min": 1, "max": 37, "mean": 19, "median": 19, "stddev": 18
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_group_by() -> Result<()> {
+        let mut buf = Vec::new();
+        let aggregates = ["count", "sum(Kit Number)", "avg(Kit Number)"]
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<Aggregate>>>()?;
+        process_csv_group_by(
+            DATA.as_bytes(),
+            &mut buf,
+            OutputFormat::Ndjson,
+            &Default::default(),
+            "Nationality",
+            &aggregates,
+        )?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "{\"Nationality\":\"Poland\",\"count\":1,\"sum(Kit Number)\":1,\"avg(Kit Number)\":1}\n\
+             {\"Nationality\":\"Italy\",\"count\":3,\"sum(Kit Number)\":56,\"avg(Kit Number)\":28}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_aggregate() {
+        assert_eq!(
+            "avg( Kit Number )".parse::<Aggregate>().unwrap(),
+            Aggregate {
+                func: AggFunc::Avg,
+                column: Some("Kit Number".to_string())
+            }
+        );
+        assert!("sum".parse::<Aggregate>().is_err());
+        assert!("median(Kit)".parse::<Aggregate>().is_err());
+        assert!("sum(Kit".parse::<Aggregate>().is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde_json::{Map, Number, Value};
+
+use crate::ColumnType;
+
+/// Converts raw csv records into typed json objects. Columns without an override
+/// get a single type, inferred from the records passed to `sample`.
+pub struct RecordTyper {
+    headers: Vec<String>,
+    types: Vec<Option<ColumnType>>,
+    infer: bool,
+    // the narrowest type every sampled cell fits, `None` while all are empty
+    inferred: Vec<Option<ColumnType>>,
+}
+
+impl RecordTyper {
+    pub fn try_new(
+        headers: &StringRecord,
+        infer: bool,
+        overrides: &[(String, ColumnType)],
+    ) -> Result<Self> {
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        let mut types = vec![None; headers.len()];
+        for (name, ty) in overrides {
+            let idx = headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown column: {}", name))?;
+            types[idx] = Some(*ty);
+        }
+        Ok(Self {
+            inferred: vec![None; headers.len()],
+            headers,
+            types,
+            infer,
+        })
+    }
+
+    pub fn infers(&self) -> bool {
+        self.infer
+    }
+
+    /// Widens the inferred column types to fit `record`.
+    pub fn sample(&mut self, record: &StringRecord) {
+        for (ty, field) in self.inferred.iter_mut().zip(record.iter()) {
+            *ty = merge_types(*ty, infer_type(field));
+        }
+    }
+
+    /// Cells that don't fit their inferred column type, which can only happen
+    /// past the sample, are kept as strings.
+    pub fn to_value(&self, record: &StringRecord) -> Result<Value> {
+        let mut map = Map::new();
+        for (i, (name, field)) in self.headers.iter().zip(record.iter()).enumerate() {
+            let value = match self.types[i] {
+                Some(ty) => convert_value(field, ty).map_err(|e| {
+                    let line = record.position().map(|p| p.line()).unwrap_or_default();
+                    anyhow::anyhow!("{} for column '{}' at line {}", e, name, line)
+                })?,
+                None if self.infer && field.is_empty() => Value::Null,
+                None if self.infer => {
+                    let ty = self.inferred[i].unwrap_or(ColumnType::String);
+                    convert_value(field, ty).unwrap_or_else(|_| Value::String(field.to_string()))
+                }
+                None => Value::String(field.to_string()),
+            };
+            map.insert(name.clone(), value);
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+/// The narrowest type holding values of both `a` and `b`.
+fn merge_types(a: Option<ColumnType>, b: Option<ColumnType>) -> Option<ColumnType> {
+    match (a, b) {
+        (None, ty) | (ty, None) => ty,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(ColumnType::Int), Some(ColumnType::Float))
+        | (Some(ColumnType::Float), Some(ColumnType::Int)) => Some(ColumnType::Float),
+        _ => Some(ColumnType::String),
+    }
+}
+
+/// Guesses the narrowest type able to hold `s`. Empty cells have no type.
+pub fn infer_type(s: &str) -> Option<ColumnType> {
+    if s.is_empty() {
+        None
+    } else if has_leading_zero(s) {
+        // identifiers such as zip codes lose information as numbers
+        Some(ColumnType::String)
+    } else if s.parse::<i64>().is_ok() {
+        Some(ColumnType::Int)
+    } else if parse_float(s).is_some() {
+        Some(ColumnType::Float)
+    } else if parse_bool(s).is_some() {
+        Some(ColumnType::Bool)
+    } else if is_iso_date(s) {
+        Some(ColumnType::Date)
+    } else {
+        Some(ColumnType::String)
+    }
+}
+
+pub fn infer_value(s: &str) -> Value {
+    match infer_type(s) {
+        None => Value::Null,
+        Some(ty) => convert_value(s, ty).unwrap_or_else(|_| Value::String(s.to_string())),
+    }
+}
+
+/// Converts `s` to `ty`. Empty cells become null for every type except string.
+/// Dates stay strings in json/yaml, but must be valid ISO 8601.
+pub fn convert_value(s: &str, ty: ColumnType) -> Result<Value> {
+    if s.is_empty() && ty != ColumnType::String {
+        return Ok(Value::Null);
+    }
+    let invalid = || anyhow::anyhow!("Invalid {} value '{}'", ty, s);
+    let value = match ty {
+        ColumnType::String => Value::String(s.to_string()),
+        ColumnType::Int => Value::Number(s.trim().parse::<i64>().map_err(|_| invalid())?.into()),
+        ColumnType::Float => {
+            let f = parse_float(s.trim()).ok_or_else(invalid)?;
+            Value::Number(Number::from_f64(f).ok_or_else(invalid)?)
+        }
+        ColumnType::Bool => Value::Bool(parse_bool(s.trim()).ok_or_else(invalid)?),
+        ColumnType::Date if is_iso_date(s.trim()) => Value::String(s.trim().to_string()),
+        ColumnType::Date => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+pub(super) fn parse_float(s: &str) -> Option<f64> {
+    // reject "inf", "NaN" and friends, which rust happily parses
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))
+    {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|f| f.is_finite())
+}
+
+fn has_leading_zero(s: &str) -> bool {
+    let digits = s.trim_start_matches(['-', '+']);
+    digits.len() > 1 && digits.starts_with('0') && digits.as_bytes()[1].is_ascii_digit()
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn is_iso_date(s: &str) -> bool {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+        || NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").is_ok()
+        || DateTime::parse_from_rfc3339(s).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_value() {
+        assert_eq!(infer_value(""), Value::Null);
+        assert_eq!(infer_value("37"), json!(37));
+        assert_eq!(infer_value("-1.5"), json!(-1.5));
+        assert_eq!(infer_value("1e3"), json!(1000.0));
+        assert_eq!(infer_value("TRUE"), json!(true));
+        assert_eq!(infer_value("inf"), json!("inf"));
+        assert_eq!(infer_value("0"), json!(0));
+        assert_eq!(infer_value("0.5"), json!(0.5));
+        assert_eq!(infer_value("00501"), json!("00501"));
+        assert_eq!(infer_value("1990-04-18"), json!("1990-04-18"));
+        assert_eq!(infer_type("1990-04-18"), Some(ColumnType::Date));
+        assert_eq!(
+            infer_type("2024-04-23T10:00:00+08:00"),
+            Some(ColumnType::Date)
+        );
+        assert_eq!(infer_type("Apr 18, 1990"), Some(ColumnType::String));
+    }
+
+    #[test]
+    fn test_convert_value() {
+        assert_eq!(convert_value("1", ColumnType::String).unwrap(), json!("1"));
+        assert_eq!(convert_value("", ColumnType::String).unwrap(), json!(""));
+        assert_eq!(convert_value("", ColumnType::Int).unwrap(), Value::Null);
+        assert_eq!(convert_value("1", ColumnType::Float).unwrap(), json!(1.0));
+        assert!(convert_value("1.5", ColumnType::Int).is_err());
+        assert!(convert_value("yes", ColumnType::Bool).is_err());
+        assert!(convert_value("Apr 18, 1990", ColumnType::Date).is_err());
+    }
+
+    #[test]
+    fn test_record_typer_infers_columns() -> Result<()> {
+        let headers = StringRecord::from(vec!["Kit Number", "Rating", "Zip", "Note"]);
+        let mut typer = RecordTyper::try_new(&headers, true, &[])?;
+        let rows = [
+            StringRecord::from(vec!["1", "7", "10121", ""]),
+            StringRecord::from(vec!["37", "7.5", "00501", ""]),
+            StringRecord::from(vec!["", "8", "10138", ""]),
+        ];
+        for row in &rows {
+            typer.sample(row);
+        }
+        assert_eq!(
+            typer.to_value(&rows[0])?,
+            json!({ "Kit Number": 1, "Rating": 7.0, "Zip": "10121", "Note": null })
+        );
+        assert_eq!(
+            typer.to_value(&rows[2])?,
+            json!({ "Kit Number": null, "Rating": 8.0, "Zip": "10138", "Note": null })
+        );
+        // past the sample
+        let row = StringRecord::from(vec!["A", "true", "1", "1"]);
+        assert_eq!(
+            typer.to_value(&row)?,
+            json!({ "Kit Number": "A", "Rating": "true", "Zip": "1", "Note": "1" })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_typer() -> Result<()> {
+        let headers = StringRecord::from(vec!["Name", "Kit Number", "Captain"]);
+        let mut typer = RecordTyper::try_new(
+            &headers,
+            true,
+            &[("Kit Number".to_string(), ColumnType::Float)],
+        )?;
+        let record = StringRecord::from(vec!["Perin", "37", "false"]);
+        typer.sample(&record);
+        assert_eq!(
+            typer.to_value(&record)?,
+            json!({ "Name": "Perin", "Kit Number": 37.0, "Captain": false })
+        );
+        assert!(
+            RecordTyper::try_new(&headers, false, &[("Age".to_string(), ColumnType::Int)]).is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,618 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{ColumnarOpts, OutputFormat};
+
+use super::{csv_columnar::ColumnarWriter, csv_from::cell};
+
+const CBOR_INDEFINITE_ARRAY: u8 = 0x9f;
+const CBOR_BREAK: u8 = 0xff;
+const MSGPACK_FIXARRAY: u8 = 0x90;
+const MSGPACK_ARRAY16: u8 = 0xdc;
+const MSGPACK_ARRAY32: u8 = 0xdd;
+const TABLE_MIN_COLUMN_WIDTH: usize = 3;
+
+pub trait RecordWriter {
+    fn write_record(&mut self, record: &Value) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+pub struct JsonWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+pub struct YamlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+pub struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+pub struct TomlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+/// MessagePack arrays carry their length up front, so encoded records are
+/// buffered until `finish`.
+pub struct MsgpackWriter<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+    count: usize,
+}
+
+pub struct CborWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+/// Writes the keys of the first record as header, later records are aligned to
+/// it. A key missing from the header is an error rather than a dropped value.
+pub struct CsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    headers: Option<Vec<String>>,
+}
+
+/// Column widths depend on every row, so records are kept until `finish`.
+pub struct TableWriter<W: Write> {
+    writer: W,
+    max_width: Option<usize>,
+    headers: Option<Vec<String>>,
+    rows: Vec<Vec<String>>,
+    numeric: Vec<bool>,
+}
+
+#[derive(Serialize)]
+struct TomlRecords<'a> {
+    records: [&'a Map<String, Value>; 1],
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> YamlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> TomlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> MsgpackWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::new(),
+            count: 0,
+        }
+    }
+}
+
+impl<W: Write> CborWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            headers: None,
+        }
+    }
+}
+
+impl<W: Write> TableWriter<W> {
+    /// Fits the table into `max_width` columns by cutting cells short.
+    pub fn new(writer: W, max_width: Option<usize>) -> Self {
+        Self {
+            writer,
+            max_width,
+            headers: None,
+            rows: Vec::new(),
+            numeric: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self, widths: &[usize], left: &str, mid: &str, right: &str) -> Result<()> {
+        let parts: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        writeln!(self.writer, "{}{}{}", left, parts.join(mid), right)?;
+        Ok(())
+    }
+
+    fn write_row(&mut self, widths: &[usize], row: &[String]) -> Result<()> {
+        write!(self.writer, "│")?;
+        for (i, (cell, &width)) in row.iter().zip(widths).enumerate() {
+            let cell = truncate(cell, width);
+            let pad = " ".repeat(width - cell.width());
+            if self.numeric[i] {
+                write!(self.writer, " {}{} │", pad, cell)?;
+            } else {
+                write!(self.writer, " {}{} │", cell, pad)?;
+            }
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for JsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let sep = if self.count == 0 { "[" } else { "," };
+        writeln!(self.writer, "{}", sep)?;
+        // json strings never contain raw newlines, so indenting line by line is safe
+        let content = serde_json::to_string_pretty(record)?;
+        for (i, line) in content.lines().enumerate() {
+            if i > 0 {
+                writeln!(self.writer)?;
+            }
+            write!(self.writer, "  {}", line)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            write!(self.writer, "[]")?;
+        } else {
+            write!(self.writer, "\n]")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for YamlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        // a one element sequence renders as a single `- ` item, so items can be appended
+        serde_yaml::to_writer(&mut self.writer, &[record])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            writeln!(self.writer, "[]")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for NdjsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for TomlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("TOML records must be tables");
+        };
+        // toml has no null, so missing values are left out of tables
+        let map = strip_nulls(map)?;
+        if self.count > 0 {
+            writeln!(self.writer)?;
+        }
+        // every record is its own `[[records]]` table, so tables can be appended
+        let content = toml::to_string(&TomlRecords { records: [&map] })?;
+        write!(self.writer, "{}", content)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            writeln!(self.writer, "records = []")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for MsgpackWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        rmp_serde::encode::write_named(&mut self.buf, record)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.count {
+            n if n < 16 => self.writer.write_all(&[MSGPACK_FIXARRAY | n as u8])?,
+            n => match u16::try_from(n) {
+                Ok(n) => {
+                    self.writer.write_all(&[MSGPACK_ARRAY16])?;
+                    self.writer.write_all(&n.to_be_bytes())?;
+                }
+                Err(_) => {
+                    let n = u32::try_from(n)
+                        .map_err(|_| anyhow::anyhow!("Too many records for a MessagePack array"))?;
+                    self.writer.write_all(&[MSGPACK_ARRAY32])?;
+                    self.writer.write_all(&n.to_be_bytes())?;
+                }
+            },
+        }
+        self.writer.write_all(&std::mem::take(&mut self.buf))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for CborWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(&[CBOR_INDEFINITE_ARRAY])?;
+        }
+        ciborium::into_writer(record, &mut self.writer)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(&[CBOR_INDEFINITE_ARRAY])?;
+        }
+        self.writer.write_all(&[CBOR_BREAK])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for CsvWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("CSV records must be objects");
+        };
+        let headers = match &self.headers {
+            Some(headers) => headers,
+            None => {
+                self.writer.write_record(map.keys())?;
+                self.headers.insert(map.keys().cloned().collect())
+            }
+        };
+        if let Some(key) = map.keys().find(|k| !headers.contains(k)) {
+            anyhow::bail!(
+                "Column '{}' is not in the CSV header, which is taken from the first record",
+                key
+            );
+        }
+        self.writer.write_record(
+            headers
+                .iter()
+                .map(|key| map.get(key).map(cell).unwrap_or_default()),
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWriter for TableWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("Table records must be objects");
+        };
+        let headers = self.headers.get_or_insert_with(|| {
+            self.numeric = vec![true; map.len()];
+            map.keys().cloned().collect()
+        });
+        let row: Vec<String> = headers
+            .iter()
+            .map(|key| map.get(key).map(cell).unwrap_or_default())
+            .map(|s| s.replace(['\n', '\r', '\t'], " "))
+            .collect();
+        for (numeric, s) in self.numeric.iter_mut().zip(&row) {
+            *numeric &= s.is_empty() || s.trim().parse::<f64>().is_ok();
+        }
+        self.rows.push(row);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let Some(headers) = self.headers.take() else {
+            return Ok(());
+        };
+        let mut widths: Vec<usize> = headers.iter().map(|h| h.width()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width());
+            }
+        }
+        // every column takes its width plus two spaces and one border
+        if let Some(max_width) = self.max_width {
+            while widths.iter().map(|w| w + 3).sum::<usize>() + 1 > max_width {
+                let Some(widest) = widths
+                    .iter_mut()
+                    .filter(|w| **w > TABLE_MIN_COLUMN_WIDTH)
+                    .max()
+                else {
+                    break;
+                };
+                *widest -= 1;
+            }
+        }
+
+        self.write_line(&widths, "┌", "┬", "┐")?;
+        self.write_row(&widths, &headers)?;
+        self.write_line(&widths, "├", "┼", "┤")?;
+        for row in std::mem::take(&mut self.rows) {
+            self.write_row(&widths, &row)?;
+        }
+        self.write_line(&widths, "└", "┴", "┘")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn record_writer<'a>(
+    writer: impl Write + 'a,
+    format: OutputFormat,
+) -> Box<dyn RecordWriter + 'a> {
+    record_writer_with(writer, format, &ColumnarOpts::default(), None)
+}
+
+/// Like `record_writer`, with the settings of parquet and arrow output and the
+/// width tables are fitted into.
+pub fn record_writer_with<'a>(
+    writer: impl Write + 'a,
+    format: OutputFormat,
+    columnar: &ColumnarOpts,
+    table_width: Option<usize>,
+) -> Box<dyn RecordWriter + 'a> {
+    match format {
+        OutputFormat::Json => Box::new(JsonWriter::new(writer)),
+        OutputFormat::Yaml => Box::new(YamlWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter::new(writer)),
+        OutputFormat::Toml => Box::new(TomlWriter::new(writer)),
+        OutputFormat::Msgpack => Box::new(MsgpackWriter::new(writer)),
+        OutputFormat::Cbor => Box::new(CborWriter::new(writer)),
+        OutputFormat::Csv => Box::new(CsvWriter::new(writer)),
+        OutputFormat::Table => Box::new(TableWriter::new(writer, table_width)),
+        OutputFormat::Parquet | OutputFormat::Arrow => {
+            Box::new(ColumnarWriter::new(writer, format, columnar.clone()))
+        }
+    }
+}
+
+/// Cuts `s` down to `width` columns, marking the cut with an ellipsis.
+fn truncate(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_string();
+    }
+    let mut ret = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or_default();
+        if used + w + 1 > width {
+            break;
+        }
+        ret.push(c);
+        used += w;
+    }
+    ret.push('…');
+    ret
+}
+
+fn strip_nulls(map: &Map<String, Value>) -> Result<Map<String, Value>> {
+    map.iter()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| Ok((k.clone(), strip_null_values(k, v)?)))
+        .collect()
+}
+
+/// Dropping a null array item would move the ones after it, so that's an error.
+fn strip_null_values(key: &str, value: &Value) -> Result<Value> {
+    let value = match value {
+        Value::Object(m) => Value::Object(strip_nulls(m)?),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, v)| match v {
+                    Value::Null => anyhow::bail!(
+                        "TOML has no null, so '{}' can't hold its missing item {}",
+                        key,
+                        i
+                    ),
+                    v => strip_null_values(key, v),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        _ => value.clone(),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::csv_types::infer_value;
+    use clap::ValueEnum;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct TomlDoc {
+        records: Vec<Value>,
+    }
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({ "Name": "Szczesny", "Kit Number": 1, "Rating": 7.5, "Captain": false }),
+            json!({ "Name": "Perin", "Kit Number": 37, "Rating": 6.0, "Captain": true }),
+        ]
+    }
+
+    fn write_all(format: OutputFormat, records: &[Value]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&mut buf, format);
+        for record in records {
+            writer.write_record(record)?;
+        }
+        writer.finish()?;
+        drop(writer);
+        Ok(buf)
+    }
+
+    fn read_all(format: OutputFormat, buf: &[u8]) -> Result<Vec<Value>> {
+        let ret = match format {
+            OutputFormat::Json => serde_json::from_slice(buf)?,
+            OutputFormat::Yaml => serde_yaml::from_slice(buf)?,
+            OutputFormat::Ndjson => serde_json::Deserializer::from_slice(buf)
+                .into_iter()
+                .collect::<Result<_, _>>()?,
+            OutputFormat::Toml => toml::from_str::<TomlDoc>(std::str::from_utf8(buf)?)?.records,
+            OutputFormat::Msgpack => rmp_serde::from_slice(buf)?,
+            OutputFormat::Cbor => ciborium::from_reader(buf)?,
+            OutputFormat::Table => unreachable!("tables are not parsed back"),
+            OutputFormat::Parquet | OutputFormat::Arrow => {
+                unreachable!("columnar output is checked in csv_columnar")
+            }
+            OutputFormat::Csv => {
+                let mut rdr = csv::Reader::from_reader(buf);
+                let headers = rdr.headers()?.clone();
+                rdr.records()
+                    .map(|record| {
+                        let record = record?;
+                        let map: Map<String, Value> = headers
+                            .iter()
+                            .zip(record.iter())
+                            .map(|(k, v)| (k.to_string(), infer_value(v)))
+                            .collect();
+                        Ok(Value::Object(map))
+                    })
+                    .collect::<Result<_>>()?
+            }
+        };
+        Ok(ret)
+    }
+
+    #[test]
+    fn test_record_writer_round_trip() -> Result<()> {
+        for format in OutputFormat::value_variants().iter().filter(|&&f| {
+            !matches!(
+                f,
+                OutputFormat::Table | OutputFormat::Parquet | OutputFormat::Arrow
+            )
+        }) {
+            for records in [records(), vec![]] {
+                let buf = write_all(*format, &records)?;
+                assert_eq!(read_all(*format, &buf)?, records, "format {}", format);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_writer() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = TableWriter::new(&mut buf, Some(28));
+        for record in records() {
+            writer.write_record(&record)?;
+        }
+        writer.finish()?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "┌──────┬──────┬──────┬─────┐\n\
+             │ Name │ Kit… │ Rat… │ Ca… │\n\
+             ├──────┼──────┼──────┼─────┤\n\
+             │ Szc… │    1 │  7.5 │ fa… │\n\
+             │ Per… │   37 │  6.0 │ tr… │\n\
+             └──────┴──────┴──────┴─────┘\n"
+        );
+        assert_eq!(truncate("张三丰", 5), "张三…");
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_writer_rejects_new_keys() -> Result<()> {
+        let buf = write_all(
+            OutputFormat::Csv,
+            &[
+                json!({ "Name": "Perin", "DOB": "1992" }),
+                json!({ "Name": "Szczesny" }),
+            ],
+        )?;
+        assert_eq!(String::from_utf8(buf)?, "Name,DOB\nPerin,1992\nSzczesny,\n");
+        let err = write_all(
+            OutputFormat::Csv,
+            &[
+                json!({ "Name": "Perin" }),
+                json!({ "Name": "Szczesny", "DOB": "1990" }),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Column 'DOB' is not in the CSV header, which is taken from the first record"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml_writer_strips_nulls() -> Result<()> {
+        let buf = write_all(
+            OutputFormat::Toml,
+            &[json!({ "Name": "Perin", "DOB": null })],
+        )?;
+        assert_eq!(String::from_utf8(buf)?, "[[records]]\nName = \"Perin\"\n");
+        let buf = write_all(
+            OutputFormat::Toml,
+            &[json!({ "clubs": [{ "name": "Genoa", "to": null }] })],
+        )?;
+        assert_eq!(
+            read_all(OutputFormat::Toml, &buf)?,
+            vec![json!({ "clubs": [{ "name": "Genoa" }] })]
+        );
+        let err =
+            write_all(OutputFormat::Toml, &[json!({ "tags": [null, null, "gk"] })]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "TOML has no null, so 'tags' can't hold its missing item 0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_msgpack_writer_array_lengths() -> Result<()> {
+        for n in [15, 16, 65536] {
+            let records: Vec<Value> = (0..n).map(|i| json!({ "Kit Number": i })).collect();
+            let buf = write_all(OutputFormat::Msgpack, &records)?;
+            assert_eq!(read_all(OutputFormat::Msgpack, &buf)?, records);
+        }
+        Ok(())
+    }
+}
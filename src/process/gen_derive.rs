@@ -0,0 +1,180 @@
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::PasswordPolicy;
+
+use super::gen_pass::char_classes;
+
+// changing any of these changes every derived password
+const SALT_PREFIX: &[u8] = b"rcli genpass derive v1";
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_ITERATIONS: u32 = 2;
+// the most HKDF-SHA256 can expand to
+const STREAM_BYTES: usize = 255 * 32;
+
+/// Derives the password for `user` at `site` from `secret`. The same inputs and
+/// policy give the same password on every machine; bump `counter` to rotate it.
+///
+/// The secret is stretched with Argon2id salted by the user, then HKDF-SHA256
+/// expands it per site and counter into bytes that pick characters the way
+/// `generate_password` does: the class minimums first, then the rest, shuffled.
+pub fn process_genpass_derive(
+    secret: &[u8],
+    site: &str,
+    user: &str,
+    counter: u32,
+    policy: &PasswordPolicy,
+) -> Result<String> {
+    anyhow::ensure!(!secret.is_empty(), "The master secret is empty");
+    anyhow::ensure!(!site.is_empty(), "The site is empty");
+    let classes = char_classes(policy)?;
+
+    let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, 1, Some(32))
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    let mut salt = SALT_PREFIX.to_vec();
+    salt.extend_from_slice(user.as_bytes());
+    let mut master = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(secret, &salt, &mut master)
+        .map_err(|e| anyhow::anyhow!("Failed to stretch the master secret: {}", e))?;
+
+    // length prefixes keep ("ab", "c") and ("a", "bc") apart
+    let mut info = Vec::new();
+    for field in [site.as_bytes(), user.as_bytes(), &counter.to_be_bytes()] {
+        info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        info.extend_from_slice(field);
+    }
+    let mut stream = vec![0u8; STREAM_BYTES];
+    Hkdf::<Sha256>::new(None, &master)
+        .expand(&info, &mut stream)
+        .map_err(|e| anyhow::anyhow!("Failed to expand the site key: {}", e))?;
+    let mut bytes = ByteStream {
+        bytes: stream.into_iter(),
+    };
+
+    let mut password = Vec::new();
+    let mut chars = Vec::new();
+    for class in &classes {
+        chars.extend_from_slice(&class.chars);
+        for _ in 0..class.min {
+            password.push(class.chars[bytes.below(class.chars.len())?]);
+        }
+    }
+    for _ in password.len()..policy.length as usize {
+        password.push(chars[bytes.below(chars.len())?]);
+    }
+    // Fisher-Yates
+    for i in (1..password.len()).rev() {
+        let j = bytes.below(i + 1)?;
+        password.swap(i, j);
+    }
+    Ok(password.into_iter().collect())
+}
+
+struct ByteStream {
+    bytes: std::vec::IntoIter<u8>,
+}
+
+impl ByteStream {
+    /// A uniform index below `n`, read from as many bytes as `n` needs. Values
+    /// past the largest multiple of `n` are skipped, so the final `% n` has no
+    /// modulo bias.
+    fn below(&mut self, n: usize) -> Result<usize> {
+        let n = u64::try_from(n)
+            .ok()
+            .filter(|n| (1..=u32::MAX as u64).contains(n))
+            .ok_or_else(|| anyhow::anyhow!("Cannot pick from {} characters", n))?;
+        let mut width = 1;
+        while 256u64.pow(width) < n {
+            width += 1;
+        }
+        let span = 256u64.pow(width);
+        let limit = span - span % n;
+        loop {
+            let mut value = 0u64;
+            for _ in 0..width {
+                let b = self.bytes.next().ok_or_else(|| {
+                    anyhow::anyhow!("Ran out of derived bytes, use a shorter password")
+                })?;
+                value = value << 8 | b as u64;
+            }
+            if value < limit {
+                return Ok((value % n) as usize);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_genpass_derive() -> Result<()> {
+        let policy = PasswordPolicy::default();
+        let secret = b"correct horse battery staple";
+        let password = process_genpass_derive(secret, "example.com", "alice", 1, &policy)?;
+        // pinned so any change to the derivation is caught
+        assert_eq!(password, "3%Zf6nL_%^5Txe$$");
+        assert_eq!(
+            password,
+            process_genpass_derive(secret, "example.com", "alice", 1, &policy)?
+        );
+        for (site, user, counter) in [
+            ("example.com", "alice", 2),
+            ("example.org", "alice", 1),
+            ("example.com", "bob", 1),
+        ] {
+            assert_ne!(
+                password,
+                process_genpass_derive(secret, site, user, counter, &policy)?
+            );
+        }
+
+        let policy = PasswordPolicy {
+            length: 24,
+            symbol: false,
+            min_number: Some(4),
+            ..Default::default()
+        };
+        let password = process_genpass_derive(secret, "example.com", "alice", 1, &policy)?;
+        assert_eq!(password.chars().count(), 24);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(password.chars().filter(|c| c.is_ascii_digit()).count() >= 4);
+        assert!(process_genpass_derive(b"", "example.com", "alice", 1, &policy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_stream_below() {
+        // with n = 100 the bytes 200..=255 would favour 0..=55, so they are skipped
+        let mut bytes = ByteStream {
+            bytes: vec![255, 200, 199, 42].into_iter(),
+        };
+        assert_eq!(bytes.below(100).unwrap(), 99);
+        assert_eq!(bytes.below(100).unwrap(), 42);
+        assert!(bytes.below(100).is_err());
+
+        // pools past 256 characters read two bytes
+        let mut bytes = ByteStream {
+            bytes: vec![0xff, 0xff, 0x01, 0x05].into_iter(),
+        };
+        assert_eq!(bytes.below(300).unwrap(), 261);
+        assert!(bytes.below(0).is_err());
+    }
+
+    #[test]
+    fn test_process_genpass_derive_large_pool() -> Result<()> {
+        let symbols: String = ('\u{100}'..'\u{300}').collect();
+        let policy = PasswordPolicy {
+            symbols: Some(symbols.clone()),
+            ..Default::default()
+        };
+        let password = process_genpass_derive(b"secret", "example.com", "alice", 1, &policy)?;
+        assert_eq!(password.chars().count(), 16);
+        assert!(password.chars().any(|c| symbols.contains(c)));
+        Ok(())
+    }
+}
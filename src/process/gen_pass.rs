@@ -1,49 +1,460 @@
-use rand::seq::SliceRandom;
-
-const UPPER: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
-const LOWER: &[u8] = b"abcdefghijkmnopqrstuvwxyz";
-const NUMBER: &[u8] = b"123456789";
-const SYMBOL: &[u8] = b"!@#$%^&*_";
-
-pub fn process_genpass(
-    length: u8,
-    upper: bool,
-    lower: bool,
-    number: bool,
-    symbol: bool,
-) -> anyhow::Result<String> {
-    let mut rng = rand::thread_rng();
+use rand::{seq::SliceRandom, Rng};
+use serde_json::json;
+use std::io::Write;
+use zxcvbn::zxcvbn;
+
+use crate::{Capitalize, OutputFormat, PassTemplate, PassphraseOpts, PasswordPolicy};
+
+use super::csv_writer::record_writer;
+
+pub(super) const UPPER: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ";
+pub(super) const LOWER: &str = "abcdefghijkmnopqrstuvwxyz";
+pub(super) const NUMBER: &str = "123456789";
+pub(super) const SYMBOL: &str = "!@#$%^&*_";
+// dice rolls and words of the EFF large wordlist, tab separated
+const WORDLIST: &str = include_str!("../../assets/eff_large_wordlist.txt");
+
+/// A generated password with its zxcvbn rating.
+#[derive(Debug, Clone)]
+pub struct RatedPassword {
+    pub password: String,
+    pub score: u8,
+    /// Estimated time to crack offline against a slow hash, e.g. "3 centuries".
+    pub crack_time: String,
+    pub entropy: f64,
+}
+
+/// A character class with the characters left after exclusions.
+pub(super) struct CharClass {
+    name: &'static str,
+    pub(super) chars: Vec<char>,
+    pub(super) min: usize,
+}
+
+pub fn process_genpass(policy: &PasswordPolicy) -> anyhow::Result<String> {
+    generate_password(policy, &mut rand::thread_rng())
+}
+
+/// Generates `count` passwords from `template` if given, passphrases when
+/// `passphrase.words` is set, or else random strings.
+pub fn process_genpass_batch(
+    policy: &PasswordPolicy,
+    passphrase: &PassphraseOpts,
+    template: Option<&PassTemplate>,
+    count: u32,
+) -> anyhow::Result<Vec<RatedPassword>> {
+    let rng = &mut rand::thread_rng();
+    let entropy = match (template, passphrase.words) {
+        (Some(template), _) => template.entropy(policy)?,
+        (None, Some(_)) => passphrase_entropy(passphrase, policy)?,
+        (None, None) => password_entropy(policy)?,
+    };
+    (0..count)
+        .map(|_| {
+            let password = match (template, passphrase.words) {
+                (Some(template), _) => template.generate(policy, rng)?,
+                (None, Some(_)) => generate_passphrase(passphrase, policy, rng)?,
+                (None, None) => generate_password(policy, rng)?,
+            };
+            rate_password(password, entropy)
+        })
+        .collect()
+}
+
+fn rate_password(password: String, entropy: f64) -> anyhow::Result<RatedPassword> {
+    let estimate = zxcvbn(&password, &[])?;
+    Ok(RatedPassword {
+        score: estimate.score(),
+        crack_time: estimate
+            .crack_times()
+            .offline_slow_hashing_1e4_per_second()
+            .to_string(),
+        password,
+        entropy,
+    })
+}
+
+/// Writes one record per password, with entropy rounded to a tenth of a bit.
+pub fn write_passwords(
+    writer: impl Write,
+    passwords: &[RatedPassword],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut writer = record_writer(writer, format);
+    for p in passwords {
+        writer.write_record(&json!({
+            "password": p.password,
+            "score": p.score,
+            "crack_time": p.crack_time,
+            "entropy": (p.entropy * 10.0).round() / 10.0,
+        }))?;
+    }
+    writer.finish()
+}
+
+/// Draws `min` characters of each class, fills up to `length` from all enabled
+/// classes and shuffles the result.
+pub fn generate_password(policy: &PasswordPolicy, rng: &mut impl Rng) -> anyhow::Result<String> {
+    let classes = char_classes(policy)?;
     let mut password = Vec::new();
     let mut chars = Vec::new();
 
-    if upper {
-        chars.extend_from_slice(UPPER);
-        password.push(*UPPER.choose(&mut rng).expect("UPPER won't be empty"));
+    for class in &classes {
+        chars.extend_from_slice(&class.chars);
+        for _ in 0..class.min {
+            password.push(*class.chars.choose(rng).expect("checked in char_classes"));
+        }
     }
 
-    if lower {
-        chars.extend_from_slice(LOWER);
-        password.push(*LOWER.choose(&mut rng).expect("LOWER won't be empty"));
+    for _ in password.len()..policy.length as usize {
+        let c = chars.choose(rng).expect("checked in char_classes");
+        password.push(*c);
     }
 
-    if number {
-        chars.extend_from_slice(NUMBER);
-        password.push(*NUMBER.choose(&mut rng).expect("NUMBER won't be empty"));
+    password.shuffle(rng);
+
+    Ok(password.into_iter().collect())
+}
+
+/// Entropy in bits of passwords generated from `policy`, over the pool of
+/// distinct characters. The minimums are ignored, so this slightly
+/// overestimates short passwords.
+pub fn password_entropy(policy: &PasswordPolicy) -> anyhow::Result<f64> {
+    let pool: usize = char_classes(policy)?.iter().map(|c| c.chars.len()).sum();
+    Ok(policy.length as f64 * (pool as f64).log2())
+}
+
+pub fn process_passphrase(
+    opts: &PassphraseOpts,
+    policy: &PasswordPolicy,
+) -> anyhow::Result<String> {
+    generate_passphrase(opts, policy, &mut rand::thread_rng())
+}
+
+/// Draws `words` words from the wordlist, then appends the extra numbers and
+/// symbols to randomly picked words.
+pub fn generate_passphrase(
+    opts: &PassphraseOpts,
+    policy: &PasswordPolicy,
+    rng: &mut impl Rng,
+) -> anyhow::Result<String> {
+    let count = word_count(opts)?;
+    let wordlist = wordlist();
+    let mut words: Vec<String> = (0..count)
+        .map(|_| {
+            let word = *wordlist.choose(rng).expect("wordlist is not empty");
+            match opts.capitalize {
+                Capitalize::None => word.to_string(),
+                Capitalize::First => capitalize(word),
+                Capitalize::All => word.to_uppercase(),
+                Capitalize::Random if rng.gen() => capitalize(word),
+                Capitalize::Random => word.to_string(),
+            }
+        })
+        .collect();
+    for (n, chars) in extra_chars(opts, policy)? {
+        for _ in 0..n {
+            let c = chars.choose(rng).expect("checked in extra_chars");
+            words[rng.gen_range(0..count)].push(*c);
+        }
     }
+    Ok(words.join(&opts.separator))
+}
 
-    if symbol {
-        chars.extend_from_slice(SYMBOL);
-        password.push(*SYMBOL.choose(&mut rng).expect("SYMBOL won't be empty"));
+/// Entropy in bits of passphrases generated from `opts`: each word adds
+/// log2(7776), a random capital one bit, and every extra character its class
+/// plus the choice of word it is appended to.
+pub fn passphrase_entropy(opts: &PassphraseOpts, policy: &PasswordPolicy) -> anyhow::Result<f64> {
+    let count = word_count(opts)? as f64;
+    let mut bits = count * (wordlist().len() as f64).log2();
+    if opts.capitalize == Capitalize::Random {
+        bits += count;
+    }
+    for (n, chars) in extra_chars(opts, policy)? {
+        bits += n as f64 * ((chars.len() as f64).log2() + count.log2());
     }
+    Ok(bits)
+}
 
-    for _ in 0..(length - password.len() as u8) {
-        let c = chars
-            .choose(&mut rng)
-            .expect("chars won't be empty in this context");
-        password.push(*c);
+pub(super) fn wordlist() -> Vec<&'static str> {
+    WORDLIST
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .collect()
+}
+
+fn word_count(opts: &PassphraseOpts) -> anyhow::Result<usize> {
+    match opts.words {
+        Some(n) if n > 0 => Ok(n as usize),
+        _ => anyhow::bail!("A passphrase needs at least one word"),
+    }
+}
+
+pub(super) fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// The numbers and symbols appended to passphrases, after exclusions.
+fn extra_chars(
+    opts: &PassphraseOpts,
+    policy: &PasswordPolicy,
+) -> anyhow::Result<Vec<(u8, Vec<char>)>> {
+    let symbols = policy.symbols.as_deref().unwrap_or(SYMBOL);
+    [
+        ("numbers", opts.add_numbers, NUMBER),
+        ("symbols", opts.add_symbols, symbols),
+    ]
+    .into_iter()
+    .filter(|(_, n, _)| *n > 0)
+    .map(|(name, n, set)| {
+        let chars = allowed_chars(set, &policy.exclude);
+        anyhow::ensure!(!chars.is_empty(), "No {} are left after exclusions", name);
+        Ok((n, chars))
+    })
+    .collect()
+}
+
+/// The distinct characters of `set` that are not excluded.
+pub(super) fn allowed_chars(set: &str, exclude: &str) -> Vec<char> {
+    let mut chars: Vec<char> = set
+        .chars()
+        .filter(|c| !c.is_whitespace() && !exclude.contains(*c))
+        .collect();
+    chars.sort_unstable();
+    chars.dedup();
+    chars
+}
+
+/// The enabled classes of `policy`, checking that their minimums can be met.
+pub(super) fn char_classes(policy: &PasswordPolicy) -> anyhow::Result<Vec<CharClass>> {
+    let symbols = policy.symbols.as_deref().unwrap_or(SYMBOL);
+    let candidates = [
+        (
+            "uppercase letters",
+            policy.uppercase,
+            policy.min_upper,
+            UPPER,
+        ),
+        (
+            "lowercase letters",
+            policy.lowercase,
+            policy.min_lower,
+            LOWER,
+        ),
+        ("numbers", policy.number, policy.min_number, NUMBER),
+        ("symbols", policy.symbol, policy.min_symbol, symbols),
+    ];
+    let mut classes: Vec<CharClass> = Vec::new();
+    for (name, enabled, min, set) in candidates {
+        if !enabled {
+            if min.is_some_and(|n| n > 0) {
+                anyhow::bail!("A minimum of {} is set, but {} are disabled", name, name);
+            }
+            continue;
+        }
+        let mut chars = allowed_chars(set, &policy.exclude);
+        let min = min.unwrap_or(1) as usize;
+        if chars.is_empty() && min > 0 {
+            anyhow::bail!("No {} are left after exclusions", name);
+        }
+        // a character in two classes would be drawn twice as often
+        chars.retain(|c| !classes.iter().any(|k| k.chars.contains(c)));
+        if chars.is_empty() && min > 0 {
+            anyhow::bail!("All {} are already in other classes", name);
+        }
+        classes.push(CharClass { name, chars, min });
+    }
+
+    if classes.iter().all(|c| c.chars.is_empty()) {
+        anyhow::bail!("No characters to generate the password from");
+    }
+    let required: usize = classes.iter().map(|c| c.min).sum();
+    if required > policy.length as usize {
+        let mins: Vec<String> = classes
+            .iter()
+            .filter(|c| c.min > 0)
+            .map(|c| format!("{} {}", c.min, c.name))
+            .collect();
+        anyhow::bail!(
+            "The minimums ({}) need {} characters, more than the length of {}",
+            mins.join(", "),
+            required,
+            policy.length
+        );
+    }
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_generate_password_minimums() -> anyhow::Result<()> {
+        let policy = PasswordPolicy {
+            length: 12,
+            min_number: Some(2),
+            min_symbol: Some(3),
+            symbols: Some("-+=".to_string()),
+            exclude: "AEIOUaeiou".to_string(),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let password = generate_password(&policy, &mut rng)?;
+            assert_eq!(password.chars().count(), 12);
+            assert!(password.chars().filter(|c| c.is_ascii_digit()).count() >= 2);
+            assert!(password.chars().filter(|c| "-+=".contains(*c)).count() >= 3);
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+            assert!(!password.chars().any(|c| "AEIOUaeiou!@#".contains(c)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_password_rejects_impossible_policies() {
+        let too_short = PasswordPolicy {
+            length: 6,
+            min_upper: Some(3),
+            min_number: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            process_genpass(&too_short).unwrap_err().to_string(),
+            "The minimums (3 uppercase letters, 1 lowercase letters, 3 numbers, 1 symbols) need 8 characters, more than the length of 6"
+        );
+        let disabled = PasswordPolicy {
+            symbol: false,
+            min_symbol: Some(2),
+            ..Default::default()
+        };
+        assert!(process_genpass(&disabled).is_err());
+        let excluded = PasswordPolicy {
+            exclude: NUMBER.to_string(),
+            ..Default::default()
+        };
+        assert!(process_genpass(&excluded).is_err());
+        let optional = PasswordPolicy {
+            exclude: NUMBER.to_string(),
+            min_number: Some(0),
+            ..Default::default()
+        };
+        assert!(process_genpass(&optional).is_ok());
+    }
+
+    #[test]
+    fn test_generate_passphrase() -> anyhow::Result<()> {
+        let words = wordlist();
+        assert_eq!(words.len(), 7776);
+        assert!(words.windows(2).all(|w| w[0] < w[1]));
+
+        let opts = PassphraseOpts {
+            words: Some(6),
+            separator: " ".to_string(),
+            capitalize: Capitalize::First,
+            add_numbers: 2,
+            add_symbols: 1,
+        };
+        let policy = PasswordPolicy {
+            symbols: Some("-+".to_string()),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            let phrase = generate_passphrase(&opts, &policy, &mut rng)?;
+            let parts: Vec<&str> = phrase.split(' ').collect();
+            assert_eq!(parts.len(), 6);
+            assert!(parts
+                .iter()
+                .all(|w| w.starts_with(|c: char| c.is_uppercase())));
+            assert_eq!(phrase.chars().filter(|c| c.is_ascii_digit()).count(), 2);
+            assert!(phrase.contains(['-', '+']));
+            let word = parts[0].trim_end_matches(|c: char| !c.is_alphabetic());
+            assert!(words.contains(&word.to_lowercase().as_str()), "{}", word);
+        }
+        Ok(())
     }
 
-    password.shuffle(&mut rng);
+    #[test]
+    fn test_write_passwords() -> anyhow::Result<()> {
+        let passphrase = PassphraseOpts {
+            words: Some(5),
+            ..Default::default()
+        };
+        let passwords = process_genpass_batch(&PasswordPolicy::default(), &passphrase, None, 3)?;
+        assert_eq!(passwords.len(), 3);
+        let mut buf = Vec::new();
+        write_passwords(&mut buf, &passwords, OutputFormat::Csv)?;
+        let ret = String::from_utf8(buf)?;
+        let rows: Vec<&str> = ret.lines().collect();
+        assert_eq!(rows[0], "password,score,crack_time,entropy");
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].starts_with(&format!("{},", passwords[0].password)));
+        assert!(rows[1].ends_with(",64.6"), "{}", rows[1]);
 
-    Ok(String::from_utf8(password)?)
+        let mut buf = Vec::new();
+        write_passwords(&mut buf, &passwords, OutputFormat::Json)?;
+        let ret: serde_json::Value = serde_json::from_slice(&buf)?;
+        assert_eq!(ret[2]["password"], passwords[2].password.as_str());
+        assert_eq!(ret[2]["score"], passwords[2].score);
+        assert_eq!(ret[2]["crack_time"], passwords[2].crack_time.as_str());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_classes() -> anyhow::Result<()> {
+        let policy = PasswordPolicy {
+            symbols: Some("aA1!".to_string()),
+            ..Default::default()
+        };
+        let classes = char_classes(&policy)?;
+        assert_eq!(classes[3].chars, vec!['!']);
+        let pool: Vec<char> = classes.iter().flat_map(|c| c.chars.clone()).collect();
+        assert_eq!(pool.len(), 24 + 25 + 9 + 1);
+        let bits = password_entropy(&policy)?;
+        assert!((bits - 16.0 * 59f64.log2()).abs() < 1e-9);
+
+        let policy = PasswordPolicy {
+            symbols: Some("aA".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            process_genpass(&policy).unwrap_err().to_string(),
+            "All symbols are already in other classes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_entropy() -> anyhow::Result<()> {
+        let policy = PasswordPolicy::default();
+        let bits = password_entropy(&policy)?;
+        assert!((bits - 16.0 * 67f64.log2()).abs() < 1e-9);
+
+        let mut opts = PassphraseOpts {
+            words: Some(6),
+            ..Default::default()
+        };
+        let bits = passphrase_entropy(&opts, &policy)?;
+        assert!((bits - 6.0 * 7776f64.log2()).abs() < 1e-9);
+        opts.capitalize = Capitalize::Random;
+        opts.add_numbers = 1;
+        let more = passphrase_entropy(&opts, &policy)?;
+        assert!((more - bits - 6.0 - 9f64.log2() - 6f64.log2()).abs() < 1e-9);
+
+        opts.words = Some(0);
+        assert!(passphrase_entropy(&opts, &policy).is_err());
+        let policy = PasswordPolicy {
+            exclude: NUMBER.to_string(),
+            ..Default::default()
+        };
+        opts.words = Some(4);
+        assert!(process_passphrase(&opts, &policy).is_err());
+        Ok(())
+    }
 }
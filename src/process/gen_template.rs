@@ -0,0 +1,256 @@
+use anyhow::Result;
+use rand::{seq::SliceRandom, Rng};
+use std::str::FromStr;
+
+use crate::PasswordPolicy;
+
+use super::gen_pass::{allowed_chars, capitalize, wordlist, LOWER, NUMBER, SYMBOL, UPPER};
+
+const VOWELS: &str = "AEIOUaeiou";
+/// The most slots a template can expand to, as for `PasswordPolicy::length`.
+const MAX_SLOTS: usize = u8::MAX as usize;
+
+/// A password pattern such as `Cvccvc-99-!!` or `{word}{digit:3}{symbol}`.
+///
+/// `C`/`c` are consonants, `V`/`v` vowels, `A`/`a` letters, `9` numbers and `!`
+/// symbols, in upper and lower case. Braces name a class with an optional
+/// count: `{upper}`, `{lower}`, `{consonant}`, `{vowel}`, `{digit}`, `{symbol}`,
+/// `{word}` and `{Word}`. Anything else, or a character after `\`, is kept as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTemplate {
+    slots: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    Literal(char),
+    Class(TemplateClass),
+    Word { capitalized: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TemplateClass {
+    Upper,
+    Lower,
+    UpperConsonant,
+    LowerConsonant,
+    UpperVowel,
+    LowerVowel,
+    Number,
+    Symbol,
+}
+
+impl PassTemplate {
+    /// Letters, numbers and symbols come from the genpass tables, which leave
+    /// out look-alikes such as `I`, `O`, `l` and `0`.
+    pub fn generate(&self, policy: &PasswordPolicy, rng: &mut impl Rng) -> Result<String> {
+        let words = wordlist();
+        let mut ret = String::new();
+        for slot in &self.slots {
+            match slot {
+                Slot::Literal(c) => ret.push(*c),
+                Slot::Class(class) => {
+                    let chars = class.chars(policy)?;
+                    ret.push(*chars.choose(rng).expect("checked in chars"));
+                }
+                Slot::Word { capitalized } => {
+                    let word = *words.choose(rng).expect("wordlist is not empty");
+                    match capitalized {
+                        true => ret.push_str(&capitalize(word)),
+                        false => ret.push_str(word),
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Entropy in bits: the sum over slots, literals add nothing.
+    pub fn entropy(&self, policy: &PasswordPolicy) -> Result<f64> {
+        let words = (wordlist().len() as f64).log2();
+        self.slots
+            .iter()
+            .map(|slot| match slot {
+                Slot::Literal(_) => Ok(0.0),
+                Slot::Class(class) => Ok((class.chars(policy)?.len() as f64).log2()),
+                Slot::Word { .. } => Ok(words),
+            })
+            .sum()
+    }
+}
+
+impl TemplateClass {
+    fn chars(self, policy: &PasswordPolicy) -> Result<Vec<char>> {
+        let symbols = policy.symbols.as_deref().unwrap_or(SYMBOL);
+        let (name, set) = match self {
+            TemplateClass::Upper => ("uppercase letters", UPPER),
+            TemplateClass::Lower => ("lowercase letters", LOWER),
+            TemplateClass::UpperConsonant => ("uppercase consonants", UPPER),
+            TemplateClass::LowerConsonant => ("lowercase consonants", LOWER),
+            TemplateClass::UpperVowel => ("uppercase vowels", UPPER),
+            TemplateClass::LowerVowel => ("lowercase vowels", LOWER),
+            TemplateClass::Number => ("numbers", NUMBER),
+            TemplateClass::Symbol => ("symbols", symbols),
+        };
+        let mut chars = allowed_chars(set, &policy.exclude);
+        match self {
+            TemplateClass::UpperConsonant | TemplateClass::LowerConsonant => {
+                chars.retain(|c| !VOWELS.contains(*c))
+            }
+            TemplateClass::UpperVowel | TemplateClass::LowerVowel => {
+                chars.retain(|c| VOWELS.contains(*c))
+            }
+            _ => {}
+        }
+        anyhow::ensure!(!chars.is_empty(), "No {} are left after exclusions", name);
+        Ok(chars)
+    }
+}
+
+impl FromStr for PassTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut slots = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let slot = match c {
+                '\\' => Slot::Literal(
+                    chars
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Template ends with an escape: {}", s))?,
+                ),
+                '{' => {
+                    let mut spec = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        spec.push(c);
+                    }
+                    anyhow::ensure!(closed, "Unclosed brace in template: {}", s);
+                    let (name, count) = match spec.split_once(':') {
+                        Some((name, count)) => (name, count.parse::<usize>().ok()),
+                        None => (spec.as_str(), Some(1)),
+                    };
+                    let slot = match name {
+                        "upper" => Slot::Class(TemplateClass::Upper),
+                        "lower" => Slot::Class(TemplateClass::Lower),
+                        "consonant" => Slot::Class(TemplateClass::LowerConsonant),
+                        "vowel" => Slot::Class(TemplateClass::LowerVowel),
+                        "digit" => Slot::Class(TemplateClass::Number),
+                        "symbol" => Slot::Class(TemplateClass::Symbol),
+                        "word" => Slot::Word { capitalized: false },
+                        "Word" => Slot::Word { capitalized: true },
+                        _ => anyhow::bail!(
+                            "Invalid template class: {{{}}}, expected upper, lower, consonant, vowel, digit, symbol, word or Word",
+                            spec
+                        ),
+                    };
+                    let count = count.filter(|n| *n > 0).ok_or_else(|| {
+                        anyhow::anyhow!("Invalid count in template: {{{}}}", spec)
+                    })?;
+                    anyhow::ensure!(
+                        slots.len() + count <= MAX_SLOTS,
+                        "Template {} is too long, at most {} parts are allowed",
+                        s,
+                        MAX_SLOTS
+                    );
+                    slots.extend(std::iter::repeat_n(slot, count));
+                    continue;
+                }
+                'C' => Slot::Class(TemplateClass::UpperConsonant),
+                'c' => Slot::Class(TemplateClass::LowerConsonant),
+                'V' => Slot::Class(TemplateClass::UpperVowel),
+                'v' => Slot::Class(TemplateClass::LowerVowel),
+                'A' => Slot::Class(TemplateClass::Upper),
+                'a' => Slot::Class(TemplateClass::Lower),
+                '9' => Slot::Class(TemplateClass::Number),
+                '!' => Slot::Class(TemplateClass::Symbol),
+                c => Slot::Literal(c),
+            };
+            anyhow::ensure!(
+                slots.len() < MAX_SLOTS,
+                "Template {} is too long, at most {} parts are allowed",
+                s,
+                MAX_SLOTS
+            );
+            slots.push(slot);
+        }
+        anyhow::ensure!(
+            slots.iter().any(|s| !matches!(s, Slot::Literal(_))),
+            "Template {} has no random parts",
+            s
+        );
+        Ok(Self { slots })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_pass_template_generate() -> Result<()> {
+        let policy = PasswordPolicy::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let template: PassTemplate = "Cvccvc-99-!!".parse()?;
+        for _ in 0..100 {
+            let password = template.generate(&policy, &mut rng)?;
+            let chars: Vec<char> = password.chars().collect();
+            assert_eq!(chars.len(), 12, "{}", password);
+            assert!(chars[0].is_ascii_uppercase() && !VOWELS.contains(chars[0]));
+            assert!(VOWELS.contains(chars[1]) && chars[1].is_lowercase());
+            assert_eq!(chars[6], '-');
+            assert!(NUMBER.contains(chars[7]) && NUMBER.contains(chars[8]));
+            assert!(SYMBOL.contains(chars[10]) && SYMBOL.contains(chars[11]));
+            assert!(!password.contains(['I', 'O', 'l', '0']));
+        }
+
+        let template: PassTemplate = r"{Word}\-{digit:3}{symbol}".parse()?;
+        let password = template.generate(&policy, &mut rng)?;
+        let (word, rest) = password.split_once('-').unwrap();
+        assert!(wordlist().contains(&word.to_lowercase().as_str()));
+        assert!(word.starts_with(|c: char| c.is_uppercase()));
+        assert_eq!(rest.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pass_template_entropy() -> Result<()> {
+        let policy = PasswordPolicy {
+            exclude: "AEU".to_string(),
+            ..Default::default()
+        };
+        let template: PassTemplate = "{word}99".parse()?;
+        let bits = template.entropy(&policy)?;
+        assert!((bits - 7776f64.log2() - 2.0 * 9f64.log2()).abs() < 1e-9);
+        let template: PassTemplate = "Vc".parse()?;
+        assert!(template.entropy(&policy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pass_template_from_str() {
+        assert!("{digits}".parse::<PassTemplate>().is_err());
+        assert!("{digit".parse::<PassTemplate>().is_err());
+        assert!("{digit:0}".parse::<PassTemplate>().is_err());
+        assert!("{digit:x}".parse::<PassTemplate>().is_err());
+        assert!("{digit:4000000000}".parse::<PassTemplate>().is_err());
+        assert!("9{digit:255}".parse::<PassTemplate>().is_err());
+        assert!("9".repeat(256).parse::<PassTemplate>().is_err());
+        assert_eq!(
+            "{digit:255}".parse::<PassTemplate>().unwrap().slots.len(),
+            255
+        );
+        assert!("abc\\".parse::<PassTemplate>().is_err());
+        assert!("--".parse::<PassTemplate>().is_err());
+        assert_eq!(
+            "\\C9".parse::<PassTemplate>().unwrap().slots,
+            vec![Slot::Literal('C'), Slot::Class(TemplateClass::Number)]
+        );
+    }
+}
@@ -1,15 +1,47 @@
 mod b64;
+mod csv_columnar;
+mod csv_diff;
+mod csv_encoding;
+mod csv_from;
+mod csv_generate;
+mod csv_join;
+mod csv_mask;
+mod csv_nested;
+mod csv_parallel;
 mod csv_process;
+mod csv_query;
+mod csv_schema;
+mod csv_sheet;
+mod csv_stats;
+mod csv_types;
+mod csv_writer;
+mod gen_derive;
 mod gen_pass;
+mod gen_template;
 mod http_serve;
 mod jwt;
+mod pass_check;
 mod text;
 
 pub use b64::{process_decode, process_encode};
-pub use csv_process::process_csv;
-pub use gen_pass::process_genpass;
+pub use csv_diff::{process_csv_diff, DiffOpts, DiffStats};
+pub use csv_from::process_csv_from;
+pub use csv_generate::{process_csv_generate, GenColumn, GenKind};
+pub use csv_join::{process_csv_concat, process_csv_join};
+pub use csv_process::{build_csv_reader, process_csv, CsvConvertOpts};
+pub use csv_query::Expr;
+pub use csv_schema::{CsvSchema, ValidationReport, Violation};
+pub use csv_sheet::{process_sheet, CellRange, SheetOpts};
+pub use csv_stats::{process_csv_group_by, process_csv_stats, AggFunc, Aggregate};
+pub use gen_derive::process_genpass_derive;
+pub use gen_pass::{
+    passphrase_entropy, password_entropy, process_genpass, process_genpass_batch,
+    process_passphrase, write_passwords, RatedPassword,
+};
+pub use gen_template::PassTemplate;
 pub use http_serve::process_http_serve;
 pub use jwt::{process_jwt_sign, process_jwt_verify};
+pub use pass_check::process_password_check;
 pub use text::{
     process_text_decrypt, process_text_encrypt, process_text_key_generate, process_text_sign,
     process_text_verify,
@@ -0,0 +1,137 @@
+use anyhow::Result;
+use std::io::Write;
+use zxcvbn::{
+    matching::{patterns::MatchPattern, Match},
+    zxcvbn,
+};
+
+/// Writes zxcvbn's analysis of `password` and returns its score. `words` are
+/// treated as a dictionary of guessable words, e.g. the user or site name.
+pub fn process_password_check(
+    password: &str,
+    words: &[String],
+    mut writer: impl Write,
+) -> Result<u8> {
+    anyhow::ensure!(!password.is_empty(), "The password is empty");
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let estimate = zxcvbn(password, &words)?;
+    let times = estimate.crack_times();
+
+    writeln!(writer, "Score: {}/4", estimate.score())?;
+    writeln!(
+        writer,
+        "Guesses: {} (10^{:.1})",
+        estimate.guesses(),
+        estimate.guesses_log10()
+    )?;
+    writeln!(writer, "Crack time:")?;
+    for (scenario, time) in [
+        (
+            "online, throttled (100/hour)",
+            times.online_throttling_100_per_hour(),
+        ),
+        (
+            "online, unthrottled (10/s)",
+            times.online_no_throttling_10_per_second(),
+        ),
+        (
+            "offline, slow hash (1e4/s)",
+            times.offline_slow_hashing_1e4_per_second(),
+        ),
+        (
+            "offline, fast hash (1e10/s)",
+            times.offline_fast_hashing_1e10_per_second(),
+        ),
+    ] {
+        writeln!(writer, "  {:<30} {}", scenario, time)?;
+    }
+    writeln!(writer, "Patterns:")?;
+    for m in estimate.sequence() {
+        writeln!(writer, "  {:<20} {}", m.token, describe(m))?;
+    }
+    if let Some(feedback) = estimate.feedback() {
+        if let Some(warning) = feedback.warning() {
+            writeln!(writer, "Warning: {}", warning)?;
+        }
+        if !feedback.suggestions().is_empty() {
+            writeln!(writer, "Suggestions:")?;
+            for suggestion in feedback.suggestions() {
+                writeln!(writer, "  - {}", suggestion)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(estimate.score())
+}
+
+fn describe(m: &Match) -> String {
+    match &m.pattern {
+        MatchPattern::Dictionary(d) => {
+            let mut ret = format!(
+                "dictionary word '{}' ({}, rank {})",
+                d.matched_word,
+                dictionary_name(&format!("{:?}", d.dictionary_name)),
+                d.rank
+            );
+            if d.reversed {
+                ret.push_str(", reversed");
+            }
+            if d.l33t {
+                ret.push_str(", l33t");
+            }
+            ret
+        }
+        MatchPattern::Spatial(s) => format!("keyboard pattern on {}", s.graph),
+        MatchPattern::Repeat(r) => format!("'{}' repeated {} times", r.base_token, r.repeat_count),
+        MatchPattern::Sequence(s) => format!("{} sequence", s.sequence_name),
+        MatchPattern::Regex(r) => format!("{} pattern", r.regex_name.replace('_', " ")),
+        MatchPattern::Date(d) => format!("date {}-{:02}-{:02}", d.year, d.month, d.day),
+        MatchPattern::BruteForce => "bruteforce".to_string(),
+    }
+}
+
+/// "UsTvAndFilm" -> "us tv and film"
+fn dictionary_name(name: &str) -> String {
+    let mut ret = String::new();
+    for c in name.chars() {
+        if c.is_uppercase() && !ret.is_empty() {
+            ret.push(' ');
+        }
+        ret.extend(c.to_lowercase());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(password: &str, words: &[&str]) -> Result<(u8, String)> {
+        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+        let mut buf = Vec::new();
+        let score = process_password_check(password, &words, &mut buf)?;
+        Ok((score, String::from_utf8(buf)?))
+    }
+
+    #[test]
+    fn test_process_password_check() -> Result<()> {
+        let (score, ret) = check("p@ssw0rd2019", &[])?;
+        assert!(score <= 1, "{}", ret);
+        assert!(ret.starts_with(&format!("Score: {}/4\n", score)));
+        assert!(ret.contains("dictionary word 'password' (passwords, rank"));
+        assert!(ret.contains("l33t"));
+        assert!(ret.contains("offline, fast hash (1e10/s)"));
+        assert!(ret.contains("Suggestions:"));
+
+        let (before, _) = check("juventusperin1992", &[])?;
+        let (after, ret) = check("juventusperin1992", &["perin"])?;
+        assert!(after <= before);
+        assert!(ret.contains("(user inputs, rank 1)"), "{}", ret);
+
+        let (score, ret) = check("kerchief-unbroken-fanfare-tidings", &[])?;
+        assert_eq!(score, 4);
+        assert!(!ret.contains("Warning:"));
+        assert!(check("", &[]).is_err());
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use chacha20poly1305::{
 };
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
-use crate::{process_genpass, TextSignFormat};
+use crate::{process_genpass, PasswordPolicy, TextSignFormat};
 
 pub trait TextSigner {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>>;
@@ -72,11 +72,21 @@ impl Blake3 {
     }
     pub fn try_new(key: impl AsRef<[u8]>) -> Result<Self> {
         let key = key.as_ref();
-        let key = (&key[..32]).try_into()?;
+        let key = key
+            .get(..32)
+            .ok_or_else(|| anyhow::anyhow!("Blake3 keys must be at least 32 bytes"))?
+            .try_into()?;
         Ok(Self::new(key))
     }
+    pub fn keyed_hash(&self, data: &[u8]) -> [u8; 32] {
+        *blake3::keyed_hash(&self.key, data).as_bytes()
+    }
     pub fn generate() -> Result<HashMap<&'static str, Vec<u8>>> {
-        let key = process_genpass(32, true, true, true, true)?;
+        let policy = PasswordPolicy {
+            length: 32,
+            ..Default::default()
+        };
+        let key = process_genpass(&policy)?;
         let mut map = HashMap::new();
         map.insert("blake3.txt", key.as_bytes().to_vec());
         Ok(map)
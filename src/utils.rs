@@ -1,5 +1,8 @@
 use anyhow::Result;
-use std::{fs::File, io::Read};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, IsTerminal, Read, Write},
+};
 
 pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     let reader: Box<dyn Read> = if input == "-" {
@@ -11,9 +14,76 @@ pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     Ok(reader)
 }
 
+pub fn get_writer(output: &str) -> Result<Box<dyn Write>> {
+    let writer: Box<dyn Write> = if output == "-" {
+        Box::new(BufWriter::new(std::io::stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(output)?))
+    };
+
+    Ok(writer)
+}
+
+/// Like `get_writer`, but refuses to truncate one of the `inputs` before it's read.
+pub fn get_writer_for(output: &str, inputs: &[&str]) -> Result<Box<dyn Write>> {
+    if output != "-" {
+        if let Ok(out) = fs::canonicalize(output) {
+            for input in inputs.iter().filter(|i| **i != "-") {
+                anyhow::ensure!(
+                    !fs::canonicalize(input).is_ok_and(|i| i == out),
+                    "Output {} is also an input, write to another file",
+                    output
+                );
+            }
+        }
+    }
+    get_writer(output)
+}
+
+/// The width of the terminal stdout is connected to, if it is.
+pub fn stdout_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    terminal_size::terminal_size()
+        .map(|(w, _)| w.0 as usize)
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+}
+
 pub fn get_content(input: &str) -> Result<Vec<u8>> {
     let mut reader = get_reader(input)?;
     let mut buf = Vec::new();
     reader.read_to_end(&mut buf)?;
     Ok(buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_writer() -> Result<()> {
+        let path = std::env::temp_dir().join("rcli_test_get_writer.txt");
+        let path = path.to_str().unwrap();
+        {
+            let mut writer = get_writer(path)?;
+            writer.write_all(b"hello")?;
+        }
+        assert_eq!(get_content(path)?, b"hello");
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_writer_for() -> Result<()> {
+        let path = std::env::temp_dir().join("rcli_test_get_writer_for.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "hello")?;
+        assert!(get_writer_for(path, &["-", path]).is_err());
+        assert_eq!(get_content(path)?, b"hello");
+        drop(get_writer_for(path, &["Cargo.toml"])?);
+        assert_eq!(get_content(path)?, b"");
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use rcli::{process_csv, OutputFormat};
+
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+// the allocation counters are process wide, so measurements must not overlap
+static MEASURE: Mutex<()> = Mutex::new(());
+
+const BUDGET: usize = 4 * 1024 * 1024;
+
+/// Produces a csv document of `rows` rows on the fly, without ever holding it in memory.
+struct GeneratedCsv {
+    rows: usize,
+    row: usize,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl GeneratedCsv {
+    fn new(rows: usize) -> Self {
+        Self {
+            rows,
+            row: 0,
+            line: b"Name,Position,DOB,Nationality,Kit Number\n".to_vec(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for GeneratedCsv {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            if self.row == self.rows {
+                return Ok(0);
+            }
+            self.line.clear();
+            self.line.extend_from_slice(
+                format!(
+                    "Player {0},Midfielder,\"Jan 1, 1990 (29)\",Italy,{1}\n",
+                    self.row,
+                    self.row % 99
+                )
+                .as_bytes(),
+            );
+            self.pos = 0;
+            self.row += 1;
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn assert_bounded_memory(rows: usize) -> anyhow::Result<()> {
+    let _guard = MEASURE.lock().unwrap();
+    for format in [OutputFormat::Json, OutputFormat::Yaml] {
+        let baseline = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        process_csv(
+            GeneratedCsv::new(rows),
+            io::sink(),
+            format,
+            &Default::default(),
+        )?;
+        let used = PEAK.load(Ordering::Relaxed) - baseline;
+        assert!(
+            used < BUDGET,
+            "{} conversion of {} rows used {} bytes, budget is {} bytes",
+            format,
+            rows,
+            used,
+            BUDGET
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_process_csv_streams_with_bounded_memory() -> anyhow::Result<()> {
+    assert_bounded_memory(50_000)
+}
+
+#[test]
+#[ignore = "converts millions of rows, run with `cargo test -- --ignored`"]
+fn test_process_csv_streams_millions_of_rows() -> anyhow::Result<()> {
+    assert_bounded_memory(3_000_000)
+}